@@ -0,0 +1,27 @@
+//! Executable for disassembling .hack binaries to .asm assembly.
+//!
+//! Usage: hack-disassembler <file.hack> [file.sym]
+
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    // Handle args.len(0). Ignore args[3..]
+    let arg = args.get(1).unwrap_or_else(|| {
+        println!("Usage: hack-disassembler <file.hack> [file.sym]");
+        std::process::exit(1);
+    });
+
+    let path_hack = Path::new(arg);
+    let path_sym = args.get(2).map(Path::new);
+
+    match assembler::disassemble(path_hack, path_sym) {
+        Ok(asm) => print!("{}", asm),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
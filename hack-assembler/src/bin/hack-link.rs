@@ -0,0 +1,52 @@
+//! Executable for linking relocatable .o objects into a .hack binary.
+//!
+//! Usage: hack-link [options] <file.o>...
+
+use std::env;
+use std::path::PathBuf;
+
+use assembler::Format;
+
+const USAGE: &str = "\
+Usage: hack-link [options] <file.o>...
+
+Objects are placed in ROM in the order given.
+
+Options:
+    -o FILE              Write the binary to FILE (default: the first object's name)
+    -f, --format=FORMAT  Binary format: hack (default), bin, ihex, memb, memh or logisim";
+
+fn main() {
+    let mut format = Format::default();
+    let mut output = None;
+    let mut objects = Vec::new();
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "-f" | "--format" => format = parse_format(&args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--format=") => format = parse_format(&arg["--format=".len()..]),
+            _ if arg.starts_with('-') => usage(),
+            _ => objects.push(PathBuf::from(arg)),
+        }
+    }
+
+    let first = objects.first().unwrap_or_else(|| usage());
+    let path_hack = output.unwrap_or_else(|| first.with_extension(format.extension()));
+
+    if let Err(e) = assembler::link(&objects, &path_hack, format) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_format(name: &str) -> Format {
+    name.parse().unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1,111 @@
+//! Disassembler: Translate .hack binaries back into .asm assembly.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::instruction::{Address, Instruction};
+use crate::symbolmap;
+use crate::symboltable::Kind;
+
+/// ROM label names and the addresses they resolve to, in file order.
+pub type Labels = Vec<(String, u16)>;
+
+/// Disassemble the file `src_hack`, naming labels from an optional symbol file.
+pub fn disassemble(src_hack: &Path, src_sym: Option<&Path>) -> Result<String, AssemblyError> {
+    let read = |path: &Path| {
+        let file = path.to_string_lossy();
+        fs::read_to_string(path).map_err(|e| AssemblyError::io(e).in_file(&file))
+    };
+
+    let labels = match src_sym {
+        Some(path) => {
+            parse_labels(&read(path)?).map_err(|e| e.in_file(&path.to_string_lossy()))?
+        },
+        None => Labels::new(),
+    };
+
+    disassemble_str(&read(src_hack)?, &labels)
+        .map_err(|e| e.in_file(&src_hack.to_string_lossy()))
+}
+
+/// Disassemble `.hack` lines of 16 '0'/'1' characters into `.asm` source.
+pub fn disassemble_str(hack: &str, labels: &Labels) -> Result<String, AssemblyError> {
+    let mut words = Vec::new();
+
+    for (i, line) in hack.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue
+        }
+
+        match u16::from_str_radix(line, 2) {
+            Ok(word) if line.len() == 16 => words.push(word),
+            _ => return Err(AssemblyError::new(ErrorKind::UnrecognisedWord, i + 1, 1, line)),
+        }
+    }
+
+    let mut asm = String::new();
+
+    for instruction in disassemble_words(&words, labels)? {
+        asm.push_str(&instruction.to_string());
+        asm.push('\n');
+    }
+
+    Ok(asm)
+}
+
+/// Decode machine words into instructions, with `(LABEL)`s inserted at the
+/// addresses they resolve to.
+///
+/// An A-instruction loading a label's address is written as `@LABEL`, unless
+/// the following instruction accesses memory (then it is a RAM address).
+/// Errors are located at line `address + 1`.
+pub fn disassemble_words(words: &[u16], labels: &Labels) -> Result<Vec<Instruction>, AssemblyError> {
+    let decoded: Vec<Instruction> = words.iter()
+        .enumerate()
+        .map(|(address, &word)| {
+            Instruction::decode(word).ok_or_else(|| {
+                let text = format!("{:0>16b}", word);
+                AssemblyError::new(ErrorKind::UnrecognisedWord, address + 1, 1, &text)
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    let label_at = |address: u16| labels.iter().find(|(_, a)| *a == address);
+    let mut instructions = Vec::new();
+
+    for (address, instruction) in decoded.iter().enumerate() {
+        for (label, _) in labels.iter().filter(|(_, a)| *a as usize == address) {
+            instructions.push(Instruction::Label(label.clone()));
+        }
+
+        let next_accesses_memory = decoded.get(address + 1)
+            .is_some_and(Instruction::accesses_memory);
+
+        match instruction {
+            Instruction::A(Address::Value(value)) if !next_accesses_memory => {
+                match label_at(*value) {
+                    Some((label, _)) => instructions.push(Instruction::A(Address::Symbol(label.clone()))),
+                    None => instructions.push(instruction.clone()),
+                }
+            },
+            _ => instructions.push(instruction.clone()),
+        }
+    }
+
+    // Labels marking the end of the program, e.g. (END) after a final jump
+    for (label, _) in labels.iter().filter(|(_, a)| *a as usize == words.len()) {
+        instructions.push(Instruction::Label(label.clone()));
+    }
+
+    Ok(instructions)
+}
+
+/// Parse a symbol file of `NAME ADDRESS [KIND]` lines, as written by the
+/// assembler. Entries with a kind other than `label` are skipped. Blank lines
+/// and `//` comments are ignored.
+pub fn parse_labels(sym: &str) -> Result<Labels, AssemblyError> {
+    symbolmap::parse(sym, Kind::Label)
+}
@@ -0,0 +1,237 @@
+//! Error: Diagnostics reported while assembling.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    InvalidSymbol,
+    UndefinedSymbol,
+    Overflow,
+    ConstantTooLarge,
+    ProgramTooLarge,
+    NotRelocatable,
+    DuplicateSymbol,
+    ConflictingPin,
+    LabelConflict,
+    InvalidObject,
+    UnrecognisedDest,
+    UnrecognisedComp,
+    UnrecognisedJump,
+    UnrecognisedWord,
+    UnrecognisedDirective,
+    UnterminatedMacro,
+    UnexpectedEndMacro,
+    MacroArguments,
+    MacroRecursion,
+    DirectiveInMacro,
+    IncludeCycle,
+}
+
+/// A line in a source file, e.g. the site of a macro expansion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+/// Failure to assemble, located at the offending text in the source.
+///
+/// `line` and `column` are 1-based. Errors that have no position in the
+/// source (e.g. failing to open the file) use line and column 0.
+#[derive(Debug)]
+pub struct AssemblyError {
+    pub kind: ErrorKind,
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    /// Macro expansions the error occurred in, innermost first.
+    pub expansions: Vec<(String, Location)>,
+}
+
+impl AssemblyError {
+    pub fn new(kind: ErrorKind, line: usize, column: usize, text: &str) -> Self {
+        AssemblyError {
+            kind,
+            file: None,
+            line,
+            column,
+            text: String::from(text),
+            expansions: Vec::new(),
+        }
+    }
+
+    pub fn io(error: io::Error) -> Self {
+        AssemblyError::new(ErrorKind::Io(error), 0, 0, "")
+    }
+
+    /// Locate the error in `file`, unless it is already known to be in
+    /// another (e.g. an included) file.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| String::from(file));
+
+        for (_, site) in self.expansions.iter_mut() {
+            site.file.get_or_insert_with(|| String::from(file));
+        }
+
+        self
+    }
+}
+
+/// Suspicious but valid assembly, reported by the lint pass.
+#[derive(Debug)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    /// Macro expansions the warning occurred in, innermost first.
+    pub expansions: Vec<(String, Location)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarningKind {
+    JumpWithoutTarget,
+    MemoryAtLabel,
+    DuplicateLabel,
+    UnusedLabel,
+    SingleUseVariable,
+    VariableInMemoryMap,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, line: usize, column: usize, text: &str) -> Self {
+        Warning {
+            kind,
+            file: None,
+            line,
+            column,
+            text: String::from(text),
+            expansions: Vec::new(),
+        }
+    }
+
+    /// Locate the warning in `file`, unless it is already known to be in
+    /// another (e.g. an included) file.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| String::from(file));
+
+        for (_, site) in self.expansions.iter_mut() {
+            site.file.get_or_insert_with(|| String::from(file));
+        }
+
+        self
+    }
+}
+
+impl From<io::Error> for AssemblyError {
+    fn from(error: io::Error) -> Self {
+        AssemblyError::io(error)
+    }
+}
+
+impl error::Error for AssemblyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+
+        if self.line > 0 {
+            write!(f, "{}:{}:", self.line, self.column)?;
+        }
+
+        write!(f, " error: {}", self.kind)?;
+
+        if !self.text.is_empty() {
+            write!(f, " '{}'", self.text)?;
+        }
+
+        for (name, site) in self.expansions.iter() {
+            write!(f, "\n    in expansion of macro '{}' at {}", name, site)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            InvalidSymbol => write!(f, "Invalid symbol"),
+            UndefinedSymbol => write!(f, "Undefined symbol"),
+            Overflow => write!(f, "Value out of range"),
+            ConstantTooLarge => write!(f, "A-instruction constant above 32767"),
+            ProgramTooLarge => write!(f, "Program longer than 32K words of ROM"),
+            NotRelocatable => write!(f, "Expression cannot be relocated at link time"),
+            DuplicateSymbol => write!(f, "Label exported by more than one object"),
+            ConflictingPin => write!(f, "Variable pinned to another address, or exported as a label"),
+            LabelConflict => write!(f, "Label has the name of a pinned variable or defined symbol"),
+            InvalidObject => write!(f, "Invalid object file"),
+            UnrecognisedDest => write!(f, "Unrecognised dest"),
+            UnrecognisedComp => write!(f, "Unrecognised comp"),
+            UnrecognisedJump => write!(f, "Unrecognised jump"),
+            UnrecognisedWord => write!(f, "Unrecognised instruction word"),
+            UnrecognisedDirective => write!(f, "Unrecognised directive"),
+            UnterminatedMacro => write!(f, "Macro has no .endm"),
+            UnexpectedEndMacro => write!(f, ".endm outside a macro"),
+            MacroArguments => write!(f, "Wrong number of macro arguments"),
+            MacroRecursion => write!(f, "Macro expands itself"),
+            DirectiveInMacro => write!(f, "Directive inside a macro"),
+            IncludeCycle => write!(f, "File includes itself"),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+
+        write!(f, "{}:{}: warning: {} '{}'", self.line, self.column, self.kind, self.text)?;
+
+        for (name, site) in self.expansions.iter() {
+            write!(f, "\n    in expansion of macro '{}' at {}", name, site)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use WarningKind::*;
+        match self {
+            JumpWithoutTarget => write!(f, "Jump without an A-instruction loading its target"),
+            MemoryAtLabel => write!(f, "Memory access at a ROM label's address"),
+            DuplicateLabel => write!(f, "Label defined twice"),
+            UnusedLabel => write!(f, "Label is never used"),
+            SingleUseVariable => write!(f, "Variable used only once, is it a typo?"),
+            VariableInMemoryMap => write!(f, "Variables allocated past 16383, into the SCREEN memory map"),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "{}", self.line),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Expression: Constant expressions in A-instructions, e.g. `@SCREEN+32`.
+//!
+//! Terms are decimal, `0x` hex or `0b` binary literals, `'c'` character
+//! literals or symbols, joined by `+` and `-`. Expressions made only of
+//! literals are folded when parsed; those naming symbols are evaluated
+//! against the SymbolTable during assembly.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::symboltable;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    terms: Vec<(Op, Term)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Value(i32),
+    Symbol(String),
+}
+
+impl Expr {
+    /// Parse `text`; errors are located by 1-based column within `text`.
+    pub fn parse(text: &str) -> Result<Self, AssemblyError> {
+        let mut terms = Vec::new();
+        let mut op = Op::Add;
+        let mut start = 0;
+        let mut quoted = false;
+
+        for (i, c) in text.char_indices() {
+            match c {
+                '\'' => quoted = !quoted,
+                '+' | '-' if !quoted => {
+                    terms.push((op, parse_term(&text[start..i], start)?));
+                    op = if c == '-' { Op::Sub } else { Op::Add };
+                    start = i + 1;
+                },
+                _ => (),
+            }
+        }
+
+        terms.push((op, parse_term(&text[start..], start)?));
+
+        Ok(Expr { terms })
+    }
+
+    /// The symbol, if the expression is just a bare symbol.
+    pub fn as_symbol(&self) -> Option<&str> {
+        match self.terms.as_slice() {
+            [(Op::Add, Term::Symbol(symbol))] => Some(symbol),
+            _ => None,
+        }
+    }
+
+    /// Names of the symbols the expression refers to.
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().filter_map(|(_, term)| match term {
+            Term::Symbol(symbol) => Some(symbol.as_str()),
+            Term::Value(_) => None,
+        })
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = (Op, &Term)> {
+        self.terms.iter().map(|(op, term)| (*op, term))
+    }
+
+    pub fn symbols_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.terms.iter_mut().filter_map(|(_, term)| match term {
+            Term::Symbol(symbol) => Some(symbol),
+            Term::Value(_) => None,
+        })
+    }
+
+    /// Value of a constant expression, if it names no symbols.
+    pub fn constant(&self) -> Option<Result<u16, ErrorKind>> {
+        match self.symbols().next() {
+            Some(_) => None,
+            None => Some(self.evaluate(|_| None).map_err(|(kind, _)| kind)),
+        }
+    }
+
+    /// Evaluate with `resolve` giving the address of each symbol. Errors
+    /// carry the offending symbol, or the whole expression on overflow.
+    pub fn evaluate<F>(&self, resolve: F) -> Result<u16, (ErrorKind, String)>
+        where F: Fn(&str) -> Option<u16>
+    {
+        let mut value: i32 = 0;
+
+        for (op, term) in self.terms.iter() {
+            let term = match term {
+                Term::Value(v) => *v,
+                Term::Symbol(s) => {
+                    resolve(s).ok_or_else(|| (ErrorKind::UndefinedSymbol, s.clone()))? as i32
+                },
+            };
+
+            value = match op {
+                Op::Add => value + term,
+                Op::Sub => value - term,
+            };
+        }
+
+        u16::try_from(value).map_err(|_| (ErrorKind::Overflow, self.to_string()))
+    }
+}
+
+fn parse_term(text: &str, offset: usize) -> Result<Term, AssemblyError> {
+    let indent = text.len() - text.trim_start().len();
+    let term = text.trim();
+    let error = |kind| AssemblyError::new(kind, 1, offset + indent + 1, term);
+
+    let literal = match term.as_bytes() {
+        [b'0', b'x', ..] | [b'0', b'X', ..] => Some(i64::from_str_radix(&term[2..], 16)),
+        [b'0', b'b', ..] | [b'0', b'B', ..] => Some(i64::from_str_radix(&term[2..], 2)),
+        [b'\'', c, b'\''] if c.is_ascii() => return Ok(Term::Value(*c as i32)),
+        [c, ..] if c.is_ascii_digit() => Some(term.parse::<i64>()),
+        _ => None,
+    };
+
+    match literal {
+        Some(Ok(value)) if value <= u16::MAX as i64 => Ok(Term::Value(value as i32)),
+        Some(Ok(_)) => Err(error(ErrorKind::Overflow)),
+        Some(Err(_)) => Err(error(ErrorKind::InvalidSymbol)),
+        None if symboltable::is_valid(term) => Ok(Term::Symbol(term.to_string())),
+        None => Err(error(ErrorKind::InvalidSymbol)),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (op, term)) in self.terms.iter().enumerate() {
+            match (i, op) {
+                (0, _) => (),
+                (_, Op::Add) => write!(f, "+")?,
+                (_, Op::Sub) => write!(f, "-")?,
+            }
+
+            match term {
+                Term::Value(v) => write!(f, "{}", v)?,
+                Term::Symbol(s) => write!(f, "{}", s)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str) -> Result<u16, (ErrorKind, String)> {
+        let resolve = |s: &str| match s {
+            "SCREEN" => Some(0x4000),
+            "LOOP" => Some(10),
+            _ => None,
+        };
+
+        Expr::parse(text).unwrap().evaluate(resolve)
+    }
+
+    #[test]
+    fn test_evaluate_literals() {
+        assert_eq!(evaluate("0x4000").ok(), Some(0x4000));
+        assert_eq!(evaluate("0b101").ok(), Some(5));
+        assert_eq!(evaluate("'A'").ok(), Some(65));
+        assert_eq!(evaluate("'+'+1").ok(), Some(44));
+    }
+
+    #[test]
+    fn test_evaluate_symbols() {
+        assert_eq!(evaluate("SCREEN+32").ok(), Some(0x4020));
+        assert_eq!(evaluate("LOOP-1").ok(), Some(9));
+        assert_eq!(evaluate("SCREEN + 0x20 - 1").ok(), Some(0x401f));
+    }
+
+    #[test]
+    fn test_evaluate_undefined_symbol() {
+        assert!(matches!(evaluate("ARRAY+3"), Err((ErrorKind::UndefinedSymbol, s)) if s == "ARRAY"));
+    }
+
+    #[test]
+    fn test_evaluate_overflow() {
+        assert!(matches!(evaluate("LOOP-11"), Err((ErrorKind::Overflow, s)) if s == "LOOP-11"));
+        assert!(matches!(evaluate("65535+1"), Err((ErrorKind::Overflow, _))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = Expr::parse("SCREEN+ 1x").unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::InvalidSymbol));
+        assert_eq!((e.column, e.text.as_str()), (9, "1x"));
+
+        let e = Expr::parse("70000").unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Overflow));
+
+        assert!(Expr::parse("SCREEN+").is_err());
+    }
+
+    #[test]
+    fn test_display_is_canonical() {
+        assert_eq!(Expr::parse("SCREEN + 0x20").unwrap().to_string(), "SCREEN+32");
+    }
+}
@@ -0,0 +1,425 @@
+//! Instruction: Typed model of a single Hack assembly command.
+//!
+//! Instructions parse from text, encode to machine words, and display as
+//! canonical assembly, so `"D=D+1".parse::<Instruction>()?.to_string()`
+//! round-trips.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::expression::Expr;
+use crate::symboltable;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    A(Address),
+    C { dest: Dest, comp: Comp, jump: Jump },
+    Label(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Value(u16),
+    Symbol(String),
+    Expr(Expr),
+}
+
+impl Instruction {
+    /// Machine word for this instruction, or `None` for labels and
+    /// A-instructions whose symbol has not been resolved.
+    pub fn encode(&self) -> Option<u16> {
+        match self {
+            Instruction::A(Address::Value(value)) => Some(*value),
+            Instruction::A(_) => None,
+            Instruction::C { dest, comp, jump } => {
+                Some(0b111 << 13 | comp.bits() << 6 | dest.bits() << 3 | jump.bits())
+            },
+            Instruction::Label(_) => None,
+        }
+    }
+
+    /// Does this C-instruction read or write M, i.e. RAM[A]?
+    pub fn accesses_memory(&self) -> bool {
+        match self {
+            Instruction::C { dest, comp, .. } => {
+                comp.bits() & 0b1000000 != 0 || dest.bits() & 0b001 != 0
+            },
+            _ => false,
+        }
+    }
+
+    /// Instruction encoded by a machine word, or `None` if the word is a
+    /// C-instruction with unused bits set or an unrecognised comp.
+    pub fn decode(word: u16) -> Option<Self> {
+        if word & 0x8000 == 0 {
+            return Some(Instruction::A(Address::Value(word)))
+        }
+
+        if word & 0xe000 != 0xe000 {
+            return None
+        }
+
+        Some(Instruction::C {
+            dest: Dest::from_bits(word >> 3 & 0b111)?,
+            comp: Comp::from_bits(word >> 6 & 0b1111111)?,
+            jump: Jump::from_bits(word & 0b111)?,
+        })
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = AssemblyError;
+
+    /// Parse a single command. Errors are reported at line 1, with the
+    /// column of the offending text within `text`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let indent = text.len() - text.trim_start().len();
+        let line = text.trim();
+        let error = |kind, offset: usize, text: &str| {
+            AssemblyError::new(kind, 1, indent + offset + 1, text)
+        };
+
+        if let Some(address) = line.strip_prefix('@') {
+            return address.parse().map(Instruction::A).map_err(|mut e: AssemblyError| {
+                e.column += indent + 1;
+                e
+            })
+        }
+
+        if let Some(symbol) = line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            if !symboltable::is_valid(symbol) {
+                return Err(error(ErrorKind::InvalidSymbol, 1, symbol))
+            }
+
+            return Ok(Instruction::Label(symbol.to_string()))
+        }
+
+        let (dest, rest, comp_offset) = match line.find('=') {
+            Some(i) => (&line[..i], &line[i + 1..], i + 1),
+            None => ("", line, 0),
+        };
+
+        let (comp, jump, jump_offset) = match rest.find(';') {
+            Some(i) => (&rest[..i], &rest[i + 1..], comp_offset + i + 1),
+            None => (rest, "", line.len()),
+        };
+
+        Ok(Instruction::C {
+            dest: dest.parse().map_err(|_| error(ErrorKind::UnrecognisedDest, 0, dest))?,
+            comp: comp.parse().map_err(|_| error(ErrorKind::UnrecognisedComp, comp_offset, comp))?,
+            jump: jump.parse().map_err(|_| error(ErrorKind::UnrecognisedJump, jump_offset, jump))?,
+        })
+    }
+}
+
+impl FromStr for Address {
+    type Err = AssemblyError;
+
+    /// Parse the operand of an A-instruction. Expressions of literals are
+    /// folded to values.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let expr = Expr::parse(text)?;
+
+        if let Some(symbol) = expr.as_symbol() {
+            return Ok(Address::Symbol(symbol.to_string()))
+        }
+
+        match expr.constant() {
+            Some(Ok(value)) => Ok(Address::Value(value)),
+            Some(Err(kind)) => Err(AssemblyError::new(kind, 1, 1, text)),
+            None => Ok(Address::Expr(expr)),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::A(address) => write!(f, "@{}", address),
+            Instruction::C { dest, comp, jump } => {
+                if *dest != Dest::Null {
+                    write!(f, "{}=", dest)?;
+                }
+
+                write!(f, "{}", comp)?;
+
+                if *jump != Jump::Null {
+                    write!(f, ";{}", jump)?;
+                }
+
+                Ok(())
+            },
+            Instruction::Label(symbol) => write!(f, "({})", symbol),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Value(value) => write!(f, "{}", value),
+            Address::Symbol(symbol) => write!(f, "{}", symbol),
+            Address::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dest {
+    Null,
+    M,
+    D,
+    MD,
+    A,
+    AM,
+    AD,
+    AMD,
+}
+
+static DESTS: &[(Dest, &str, u16)] = &[
+    (Dest::Null, "",    0b000),
+    (Dest::M,    "M",   0b001),
+    (Dest::D,    "D",   0b010),
+    (Dest::MD,   "MD",  0b011),
+    (Dest::A,    "A",   0b100),
+    (Dest::AM,   "AM",  0b101),
+    (Dest::AD,   "AD",  0b110),
+    (Dest::AMD,  "AMD", 0b111),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    A,
+    NotD,
+    NotA,
+    NegD,
+    NegA,
+    DPlusOne,
+    APlusOne,
+    DMinusOne,
+    AMinusOne,
+    DPlusA,
+    DMinusA,
+    AMinusD,
+    DAndA,
+    DOrA,
+    M,
+    NotM,
+    NegM,
+    MPlusOne,
+    MMinusOne,
+    DPlusM,
+    DMinusM,
+    MMinusD,
+    DAndM,
+    DOrM,
+}
+
+// a-bit followed by c1..c6
+static COMPS: &[(Comp, &str, u16)] = &[
+    (Comp::Zero,      "0",   0b0101010),
+    (Comp::One,       "1",   0b0111111),
+    (Comp::MinusOne,  "-1",  0b0111010),
+    (Comp::D,         "D",   0b0001100),
+    (Comp::A,         "A",   0b0110000),
+    (Comp::NotD,      "!D",  0b0001101),
+    (Comp::NotA,      "!A",  0b0110001),
+    (Comp::NegD,      "-D",  0b0001111),
+    (Comp::NegA,      "-A",  0b0110011),
+    (Comp::DPlusOne,  "D+1", 0b0011111),
+    (Comp::APlusOne,  "A+1", 0b0110111),
+    (Comp::DMinusOne, "D-1", 0b0001110),
+    (Comp::AMinusOne, "A-1", 0b0110010),
+    (Comp::DPlusA,    "D+A", 0b0000010),
+    (Comp::DMinusA,   "D-A", 0b0010011),
+    (Comp::AMinusD,   "A-D", 0b0000111),
+    (Comp::DAndA,     "D&A", 0b0000000),
+    (Comp::DOrA,      "D|A", 0b0010101),
+    (Comp::M,         "M",   0b1110000),
+    (Comp::NotM,      "!M",  0b1110001),
+    (Comp::NegM,      "-M",  0b1110011),
+    (Comp::MPlusOne,  "M+1", 0b1110111),
+    (Comp::MMinusOne, "M-1", 0b1110010),
+    (Comp::DPlusM,    "D+M", 0b1000010),
+    (Comp::DMinusM,   "D-M", 0b1010011),
+    (Comp::MMinusD,   "M-D", 0b1000111),
+    (Comp::DAndM,     "D&M", 0b1000000),
+    (Comp::DOrM,      "D|M", 0b1010101),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jump {
+    Null,
+    JGT,
+    JEQ,
+    JGE,
+    JLT,
+    JNE,
+    JLE,
+    JMP,
+}
+
+static JUMPS: &[(Jump, &str, u16)] = &[
+    (Jump::Null, "",    0b000),
+    (Jump::JGT,  "JGT", 0b001),
+    (Jump::JEQ,  "JEQ", 0b010),
+    (Jump::JGE,  "JGE", 0b011),
+    (Jump::JLT,  "JLT", 0b100),
+    (Jump::JNE,  "JNE", 0b101),
+    (Jump::JLE,  "JLE", 0b110),
+    (Jump::JMP,  "JMP", 0b111),
+];
+
+#[derive(Debug)]
+pub struct ParseMnemonicError;
+
+// Mnemonic <-> binary conversions driven by the tables above
+macro_rules! mnemonic_table {
+    ($type:ty, $table:ident) => {
+        impl $type {
+            pub fn bits(self) -> u16 {
+                $table.iter().find(|(t, _, _)| *t == self).unwrap().2
+            }
+
+            pub fn from_bits(bits: u16) -> Option<Self> {
+                $table.iter().find(|(_, _, b)| *b == bits).map(|(t, _, _)| *t)
+            }
+        }
+
+        impl FromStr for $type {
+            type Err = ParseMnemonicError;
+
+            fn from_str(mnemonic: &str) -> Result<Self, Self::Err> {
+                $table.iter()
+                      .find(|(_, m, _)| *m == mnemonic)
+                      .map(|(t, _, _)| *t)
+                      .ok_or(ParseMnemonicError)
+            }
+        }
+
+        impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mnemonic = $table.iter().find(|(t, _, _)| t == self).unwrap().1;
+                write!(f, "{}", mnemonic)
+            }
+        }
+    }
+}
+
+mnemonic_table!(Dest, DESTS);
+mnemonic_table!(Comp, COMPS);
+mnemonic_table!(Jump, JUMPS);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Instruction {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_a_value() {
+        assert_eq!(parse("@42"), Instruction::A(Address::Value(42)));
+    }
+
+    #[test]
+    fn test_parse_a_symbol() {
+        assert_eq!(parse("@LOOP"), Instruction::A(Address::Symbol(String::from("LOOP"))));
+    }
+
+    #[test]
+    fn test_parse_a_expression() {
+        assert_eq!(parse("@0x4000"), Instruction::A(Address::Value(0x4000)));
+        assert_eq!(parse("@'A'+1"), Instruction::A(Address::Value(66)));
+        assert!(matches!(parse("@SCREEN+32"), Instruction::A(Address::Expr(_))));
+    }
+
+    #[test]
+    fn test_parse_a_error_column() {
+        let e = "  @SCREEN+x!".parse::<Instruction>().unwrap_err();
+
+        assert!(matches!(e.kind, ErrorKind::InvalidSymbol));
+        assert_eq!((e.column, e.text.as_str()), (11, "x!"));
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse("(LOOP)"), Instruction::Label(String::from("LOOP")));
+    }
+
+    #[test]
+    fn test_parse_c() {
+        assert_eq!(parse("AM=M-1"), Instruction::C { dest: Dest::AM, comp: Comp::MMinusOne, jump: Jump::Null });
+        assert_eq!(parse("0;JMP"), Instruction::C { dest: Dest::Null, comp: Comp::Zero, jump: Jump::JMP });
+        assert_eq!(parse("D=D-A;JGT"), Instruction::C { dest: Dest::D, comp: Comp::DMinusA, jump: Jump::JGT });
+    }
+
+    #[test]
+    fn test_parse_error_column() {
+        let e = "  MD=D+2;JMP".parse::<Instruction>().unwrap_err();
+
+        assert!(matches!(e.kind, ErrorKind::UnrecognisedComp));
+        assert_eq!((e.line, e.column, e.text.as_str()), (1, 6, "D+2"));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(parse("@7").encode(), Some(7));
+        assert_eq!(parse("D=A").encode(), Some(0b1110110000010000));
+        assert_eq!(parse("!A").encode(), Some(0b1110110001000000));
+        assert_eq!(parse("@LOOP").encode(), None);
+        assert_eq!(parse("(LOOP)").encode(), None);
+    }
+
+    #[test]
+    fn test_not_a_and_not_m_differ_from_negation() {
+        // !A was once encoded as -A, 0110011
+        assert_eq!(parse("D=!A").encode(), Some(0b1110110001010000));
+        assert_eq!(parse("D=-A").encode(), Some(0b1110110011010000));
+        assert_eq!(parse("D=!M").encode(), Some(0b1111110001010000));
+        assert_eq!(parse("D=-M").encode(), Some(0b1111110011010000));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(7), Some(parse("@7")));
+        assert_eq!(Instruction::decode(0b1110110000010000), Some(parse("D=A")));
+        assert_eq!(Instruction::decode(0b1111110111001000), Some(parse("M=M+1")));
+        assert_eq!(Instruction::decode(0b1000110000010000), None);
+        assert_eq!(Instruction::decode(0b1110000001000000), None);
+    }
+
+    #[test]
+    fn test_decode_inverts_encode() {
+        for (comp, _, _) in COMPS {
+            for (dest, _, _) in DESTS {
+                for (jump, _, _) in JUMPS {
+                    let c = Instruction::C { dest: *dest, comp: *comp, jump: *jump };
+                    assert_eq!(Instruction::decode(c.encode().unwrap()), Some(c));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_display_is_canonical() {
+        for text in &["@42", "@R13", "(END)", "M=D", "AMD=!M", "D;JLE", "0;JMP", "A=D|A;JNE"] {
+            assert_eq!(parse(text).to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn test_mnemonic_bits_are_unique() {
+        for (i, (_, _, bits)) in COMPS.iter().enumerate() {
+            assert!(COMPS[i + 1..].iter().all(|(_, _, b)| b != bits));
+        }
+    }
+}
@@ -1,88 +1,337 @@
-/// Assembler: Library for assembling .hack binaries from .asm assembly.
+//! Assembler: Library for assembling .hack binaries from .asm assembly.
 
 use std::fs;
-use std::io::{prelude::*, BufWriter};
+use std::io::prelude::*;
 use std::path::Path;
 
-mod code;
+mod disassembler;
+mod error;
+mod expression;
+pub mod instruction;
+mod linker;
+mod lint;
+mod listing;
+mod object;
+mod optimizer;
+mod output;
 mod parser;
-mod symboltable;
+mod preprocessor;
+mod symbolmap;
+pub mod symboltable;
 
-use parser::{Parser, CommandType};
-use symboltable::SymbolTable;
+pub use disassembler::{disassemble, disassemble_str, disassemble_words, parse_labels, Labels};
+pub use error::{AssemblyError, ErrorKind, Warning, WarningKind};
+pub use instruction::Instruction;
+pub use object::{Object, Relocation};
+pub use output::{write_words, Format};
+pub use symbolmap::{parse_variables, SymbolFormat};
 
-pub fn assemble(src_asm: &Path, dst_hack: &Path) -> Result<(), std::io::Error> {
+use instruction::Address;
+use listing::Listing;
+use parser::Command;
+use preprocessor::Preprocessed;
+use symboltable::{Kind, SymbolTable};
+
+/// Words of ROM, and the largest constant an A-instruction can hold.
+const ROM_SIZE: usize = 0x8000;
+const MAX_CONSTANT: u16 = 0x7fff;
+
+/// Base of the SCREEN and KBD memory maps, above the data memory.
+const MEMORY_MAP: u16 = 0x4000;
+
+/// Output format, and additional outputs written alongside the binary.
+#[derive(Default)]
+pub struct Options {
+    /// Encoding of the assembled binary.
+    pub format: Format,
+    /// Write a `.lst` listing of ROM addresses, words and source lines.
+    pub listing: bool,
+    /// Write a `.sym` map of labels, variables and predefined symbols.
+    pub symbols: Option<SymbolFormat>,
+    /// Report suspicious but valid assembly as warnings.
+    pub lint: bool,
+    /// Write a relocatable object for `link`, instead of a binary.
+    pub object: bool,
+    /// Symbols defined alongside the predefined symbols, e.g. `DEBUG=1`.
+    pub defines: Vec<(String, u16)>,
+    /// Variables pinned to fixed RAM addresses, as well as by `.pin`.
+    pub variables: Vec<(String, u16)>,
+    /// Shrink the program with peephole optimizations.
+    pub optimize: bool,
+}
+
+/// Assemble the file `src_asm`, writing the binary to `dst_hack`.
+pub fn assemble(src_asm: &Path, dst_hack: &Path) -> Result<(), AssemblyError> {
+    assemble_with(src_asm, dst_hack, &Options::default()).map(|_| ())
+}
+
+/// Assemble the file `src_asm`, writing the binary to `dst_hack` in the
+/// chosen format and any additional outputs next to it. Returns warnings,
+/// including those of the lint pass if enabled.
+pub fn assemble_with(src_asm: &Path, dst_hack: &Path, options: &Options) -> Result<Vec<Warning>, AssemblyError> {
+    let asm = src_asm.to_string_lossy();
+
+    let assembly = fs::read_to_string(src_asm)
+        .map_err(|e| AssemblyError::io(e).in_file(&asm))?;
+
+    if options.object {
+        let source = preprocessor::preprocess(&assembly, Some(src_asm)).map_err(|e| e.in_file(&asm))?;
+        let symbol_table = symbol_table(&source, options);
+        let object = object::compile(&source.source, &symbol_table, options.optimize)
+            .map_err(|e| source.locate(e).in_file(&asm))?;
+
+        create(dst_hack, |file| object.write(file))?;
+
+        return Ok(Vec::new())
+    }
+
+    let program = assemble_program(&assembly, Some(src_asm), options)
+        .map_err(|e| e.in_file(&asm))?;
+
+    create(dst_hack, |file| write_words(&program.words, options.format, file))?;
+
+    if options.listing {
+        let dst_lst = dst_hack.with_extension("lst");
+        create(&dst_lst, |file| program.listing.write(&program.source, file))?;
+    }
+
+    if let Some(format) = options.symbols {
+        let dst_sym = dst_hack.with_extension("sym");
+        create(&dst_sym, |file| symbolmap::write(&program.symbol_table, format, file))?;
+    }
+
+    let mut warnings = program.warnings;
+
+    if options.lint {
+        let symbol_table = symbol_table(&program.source, options);
+        warnings.extend(lint::lint(&program.source, &symbol_table).map_err(|e| e.in_file(&asm))?);
+    }
+
+    Ok(warnings.into_iter().map(|w| w.in_file(&asm)).collect())
+}
+
+fn create<F>(path: &Path, write: F) -> Result<(), AssemblyError>
+    where F: FnOnce(fs::File) -> Result<(), AssemblyError>
+{
+    fs::File::create(path)
+        .map_err(AssemblyError::io)
+        .and_then(write)
+        .map_err(|e| e.in_file(&path.to_string_lossy()))
+}
+
+/// Assemble the file `src_asm` into machine words, with the symbol table
+/// they were resolved by, e.g. for debuggers to name addresses.
+pub fn assemble_file(src_asm: &Path) -> Result<(Vec<u16>, SymbolTable), AssemblyError> {
+    let asm = src_asm.to_string_lossy();
+
+    let assembly = fs::read_to_string(src_asm)
+        .map_err(|e| AssemblyError::io(e).in_file(&asm))?;
+
+    let program = assemble_program(&assembly, Some(src_asm), &Options::default())
+        .map_err(|e| e.in_file(&asm))?;
+
+    Ok((program.words, program.symbol_table))
+}
+
+/// Assemble `.asm` read from `src`, writing `.hack` lines to `dst`.
+pub fn assemble_io(mut src: impl Read, dst: impl Write) -> Result<(), AssemblyError> {
+    let mut assembly = String::new();
+    src.read_to_string(&mut assembly)?;
+
+    let words = assemble_str(&assembly)?;
+
+    write_words(&words, Format::Hack, dst)
+}
+
+/// Assemble `.asm` source into Hack machine words. Files are included
+/// relative to the working directory.
+pub fn assemble_str(assembly: &str) -> Result<Vec<u16>, AssemblyError> {
+    assemble_program(assembly, None, &Options::default()).map(|program| program.words)
+}
+
+/// Assemble `.asm` source into a relocatable object.
+pub fn compile_str(assembly: &str) -> Result<Object, AssemblyError> {
+    let source = preprocessor::preprocess(assembly, None)?;
+
+    let symbol_table = symbol_table(&source, &Options::default());
+
+    object::compile(&source.source, &symbol_table, false).map_err(|e| source.locate(e))
+}
+
+/// Link the object files `src_objects`, in order, writing the binary to
+/// `dst_hack` in the chosen format.
+pub fn link<P: AsRef<Path>>(src_objects: &[P], dst_hack: &Path, format: Format) -> Result<(), AssemblyError> {
+    let mut objects = Vec::new();
+
+    for path in src_objects.iter() {
+        let file = path.as_ref().to_string_lossy().into_owned();
+        let object = fs::read_to_string(path)
+            .map_err(AssemblyError::io)
+            .and_then(|text| text.parse::<Object>())
+            .map_err(|e| e.in_file(&file))?;
+
+        objects.push((file, object));
+    }
+
+    let words = link_objects(&objects)?;
+
+    create(dst_hack, |file| write_words(&words, format, file))
+}
+
+/// Link objects, each named by the file it came from, into machine words.
+pub fn link_objects(objects: &[(String, Object)]) -> Result<Vec<u16>, AssemblyError> {
+    let objects: Vec<_> = objects.iter().map(|(file, object)| (Some(file.as_str()), object)).collect();
+
+    linker::link(&objects)
+}
+
+/// Lint `.asm` source, without assembling it.
+pub fn lint_str(assembly: &str) -> Result<Vec<Warning>, AssemblyError> {
+    let source = preprocessor::preprocess(assembly, None)?;
+
+    let symbol_table = symbol_table(&source, &Options::default());
+
+    lint::lint(&source, &symbol_table)
+}
+
+struct Program {
+    source: Preprocessed,
+    words: Vec<u16>,
+    listing: Listing,
+    symbol_table: SymbolTable,
+    warnings: Vec<Warning>,
+}
+
+fn assemble_program(assembly: &str, path: Option<&Path>, options: &Options) -> Result<Program, AssemblyError> {
+    let source = preprocessor::preprocess(assembly, path)?;
+    let symbol_table = symbol_table(&source, options);
+
+    match assemble_source(&source.source, symbol_table, options.optimize) {
+        Ok((words, listing, symbol_table, warnings)) => {
+            let warnings = warnings.into_iter().map(|w| source.locate_warning(w)).collect();
+            Ok(Program { source, words, listing, symbol_table, warnings })
+        },
+        Err(e) => Err(source.locate(e)),
+    }
+}
+
+type Assembled = (Vec<u16>, Listing, SymbolTable, Vec<Warning>);
+
+// Predefined symbols, with those defined and pinned by the options and source
+fn symbol_table(source: &Preprocessed, options: &Options) -> SymbolTable {
     let mut symbol_table = SymbolTable::new();
 
-    // First Pass
-    let assembly = fs::File::open(src_asm)?;
+    for (name, value) in options.defines.iter() {
+        symbol_table.addEntry(name, *value, Kind::Defined);
+    }
+
+    for (name, address) in options.variables.iter().chain(source.pins.iter()) {
+        symbol_table.pin(name, *address);
+    }
+
+    symbol_table
+}
 
-    let mut parser = Parser::new(assembly);
+fn assemble_source(assembly: &str, mut symbol_table: SymbolTable, optimize: bool) -> Result<Assembled, AssemblyError> {
+    let mut commands = parser::parse(assembly)?;
+
+    if optimize {
+        commands = optimizer::optimize(commands);
+    }
+
+    // First Pass
     let mut rom_address = 0;
 
-    parser.advance();
+    for command in commands.iter() {
+        match &command.instruction {
+            Instruction::Label(symbol) => {
+                if let Some((_, Kind::Variable | Kind::Defined)) = symbol_table.hash.get(symbol) {
+                    return Err(AssemblyError::new(ErrorKind::LabelConflict, command.line, command.column + 1, symbol))
+                }
 
-    while parser.hasMoreCommands() {
-        match parser.commandType() {
-            CommandType::L_COMMAND => {
-                symbol_table.addEntry(&parser.symbol(), rom_address)
-            }
+                symbol_table.addEntry(symbol, rom_address, Kind::Label)
+            },
+            _ if rom_address as usize == ROM_SIZE => {
+                let text = command.instruction.to_string();
+                return Err(AssemblyError::new(ErrorKind::ProgramTooLarge, command.line, command.column, &text))
+            },
             _ => rom_address += 1,
         }
-
-        parser.advance();
     }
 
     // Second Pass
-    let assembly = fs::File::open(src_asm)?;
-    let hack = fs::File::create(dst_hack)?;
-
-    let mut parser = Parser::new(assembly);
-    let mut writer = BufWriter::new(hack);
+    let mut words = Vec::with_capacity(rom_address as usize);
+    let mut listing = Listing::new();
+    let mut warnings = Vec::new();
     let mut ram_address = SymbolTable::NEXT_AVAILABLE_RAM_ADDRESS;
+    let mut in_memory_map = false;
 
-    parser.advance();
+    for command in commands.iter() {
+        let word = match &command.instruction {
+            Instruction::A(address) => {
+                let column = command.column + 1;
 
-    while parser.hasMoreCommands() {
-        let binary = match parser.commandType() {
-            CommandType::A_COMMAND => {
-                let symbol = parser.symbol();
+                let address = match address {
+                    Address::Value(address) => *address,
+                    Address::Symbol(symbol) => match symbol_table.GetAddress(symbol) {
+                        Some(address) => address,
+                        None if symboltable::is_scoped(symbol) => {
+                            let text = symboltable::unscoped(symbol);
+                            return Err(AssemblyError::new(ErrorKind::UndefinedSymbol, command.line, column, text))
+                        },
+                        None => {
+                            let address = symbol_table.allocate(symbol, &mut ram_address);
 
-                let address = match symbol.parse::<u16>() {
-                    Ok(address) => address,
-                    Err(_) => {
-                        if !symbol_table.contains(&symbol) {
-                            symbol_table.addEntry(&symbol, ram_address);
-                            ram_address += 1;
-                        }
+                            // Once, at the first past it: pinned addresses may be skipped over
+                            if address >= MEMORY_MAP && !in_memory_map {
+                                let kind = WarningKind::VariableInMemoryMap;
+                                warnings.push(Warning::new(kind, command.line, column, symbol));
+                                in_memory_map = true;
+                            }
 
-                        symbol_table.GetAddress(&symbol)
-                    }
+                            address
+                        },
+                    },
+                    Address::Expr(expr) => {
+                        expr.evaluate(|s| symbol_table.GetAddress(s)).map_err(|(kind, text)| {
+                            let operand = operand(assembly, command);
+                            let text = match kind {
+                                ErrorKind::UndefinedSymbol => symboltable::unscoped(&text),
+                                _ => operand,
+                            };
+                            let offset = operand.find(text).unwrap_or(0);
+                            AssemblyError::new(kind, command.line, column + offset, text)
+                        })?
+                    },
                 };
 
-                Some(format!("{:0>16b}", address))
-            },
-            CommandType::C_COMMAND => {
-                Some(format!("111{}{}{}", code::comp(&parser.comp()),
-                                          code::dest(&parser.dest()),
-                                          code::jump(&parser.jump()),
-                ))
-            },
-            CommandType::L_COMMAND => {
-                None
+                if address > MAX_CONSTANT {
+                    let text = operand(assembly, command);
+                    return Err(AssemblyError::new(ErrorKind::ConstantTooLarge, command.line, column, text))
+                }
+
+                Some(address)
             },
+            instruction => instruction.encode(),
         };
 
-        // println!("{:<5} -> {}", parser.line, binary);
-
-        if let Some(binary) = binary {
-            writeln!(&mut writer, "{}", binary)?;
+        match &command.instruction {
+            Instruction::Label(label) if symboltable::is_scoped(label) => {
+                listing.add_local(command.line, words.len(), symboltable::readable(label))
+            },
+            _ => listing.add(command.line, words.len(), word),
         }
 
-        parser.advance();
+        if let Some(word) = word {
+            words.push(word);
+        }
     }
 
-    writer.flush()?;
+    Ok((words, listing, symbol_table, warnings))
+}
 
-    Ok(())
+// Source text of an A-instruction's operand, for diagnostics
+fn operand<'a>(assembly: &'a str, command: &Command) -> &'a str {
+    let line = assembly.lines().nth(command.line - 1).unwrap_or("");
+    line.get(command.column..).unwrap_or("").split("//").next().unwrap().trim()
 }
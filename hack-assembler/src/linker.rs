@@ -0,0 +1,162 @@
+//! Linker: Combine relocatable objects into one program.
+//!
+//! Objects are placed in ROM in the order given, so the first should hold
+//! the program's entry point. Imports are resolved to the labels other
+//! objects export, or pinned variables, or else allocated as variables
+//! from RAM[16]. A variable may not be pinned to two addresses, nor share
+//! its name with an exported label.
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::object::Object;
+use crate::symboltable::{Kind, SymbolTable};
+use crate::{MAX_CONSTANT, ROM_SIZE};
+
+/// Link `objects`, each with the file it was read from, into machine words.
+pub fn link(objects: &[(Option<&str>, &Object)]) -> Result<Vec<u16>, AssemblyError> {
+    let error = |kind, file: Option<&str>, text: &str| {
+        let mut e = AssemblyError::new(kind, 0, 0, text);
+        e.file = file.map(String::from);
+        e
+    };
+
+    // Place each object after the last
+    let mut bases = Vec::new();
+    let mut size = 0;
+
+    for (file, object) in objects.iter() {
+        bases.push(size as u16);
+        size += object.code.len();
+
+        if size > ROM_SIZE {
+            return Err(error(ErrorKind::ProgramTooLarge, *file, ""))
+        }
+    }
+
+    let mut symbol_table = SymbolTable::new();
+
+    for (file, object) in objects.iter() {
+        for (name, address) in object.pins.iter() {
+            match symbol_table.hash.get(name) {
+                Some((pinned, Kind::Variable)) if pinned != address => {
+                    return Err(error(ErrorKind::ConflictingPin, *file, name))
+                },
+                _ => symbol_table.pin(name, *address),
+            }
+        }
+    }
+
+    for ((file, object), base) in objects.iter().zip(bases.iter()) {
+        for (name, address) in object.exports.iter() {
+            match symbol_table.hash.get(name) {
+                Some((_, Kind::Label)) => return Err(error(ErrorKind::DuplicateSymbol, *file, name)),
+                Some((_, Kind::Variable)) => return Err(error(ErrorKind::ConflictingPin, *file, name)),
+                _ => (),
+            }
+
+            symbol_table.addEntry(name, base + address, Kind::Label);
+        }
+    }
+
+    let mut words = Vec::with_capacity(size);
+    let mut ram_address = SymbolTable::NEXT_AVAILABLE_RAM_ADDRESS;
+
+    for ((file, object), base) in objects.iter().zip(bases.iter()) {
+        let mut code = object.code.clone();
+
+        for relocation in object.relocations.iter() {
+            let (name, value) = match relocation.import {
+                None => ("", *base),
+                Some(i) => {
+                    let name = object.imports[i].as_str();
+
+                    if !symbol_table.contains(name) {
+                        symbol_table.allocate(name, &mut ram_address);
+                    }
+
+                    (name, symbol_table.GetAddress(name).unwrap())
+                },
+            };
+
+            let word = &mut code[relocation.address as usize];
+            *word = word.wrapping_add(value);
+
+            if *word > MAX_CONSTANT {
+                return Err(error(ErrorKind::ConstantTooLarge, *file, name))
+            }
+        }
+
+        words.extend(code);
+    }
+
+    Ok(words)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::compile;
+    use crate::preprocessor::preprocess;
+
+    fn link_str(sources: &[&str]) -> Result<Vec<u16>, AssemblyError> {
+        let objects: Vec<Object> = sources.iter().map(|s| compile(s, &SymbolTable::new(), false).unwrap()).collect();
+        let objects: Vec<(Option<&str>, &Object)> = objects.iter().map(|o| (None, o)).collect();
+
+        link(&objects)
+    }
+
+    #[test]
+    fn test_link_relocates_labels_and_imports() {
+        let words = link_str(&["@MAIN\n0;JMP\n", "@x\n(MAIN)\n@MAIN\n@y\n@x\n"]).unwrap();
+
+        assert_eq!(words, vec!(3, 0xea87, 16, 3, 17, 16));
+    }
+
+    #[test]
+    fn test_link_pinned_variables() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.pin("result", 16);
+
+        let pinned = compile("@result\n@x\n", &symbol_table, false).unwrap();
+        let other = compile("@result\n", &SymbolTable::new(), false).unwrap();
+
+        assert_eq!(link(&[(None, &pinned), (None, &other)]).unwrap(), vec!(16, 17, 16));
+    }
+
+    #[test]
+    fn test_link_objects_expanding_the_same_macro() {
+        let wait = ".macro WAIT\n(LOOP)\n@LOOP\n0;JMP\n.endm\n";
+        let sources = [format!("{}(MAIN)\nWAIT\n", wait), format!("{}(NEXT)\nWAIT\n", wait)];
+        let sources: Vec<String> = sources.iter().map(|s| preprocess(s, None).unwrap().source).collect();
+        let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+
+        assert_eq!(link_str(&sources).unwrap(), vec!(0, 0xea87, 2, 0xea87));
+    }
+
+    #[test]
+    fn test_link_duplicate_export() {
+        let e = link_str(&["(MAIN)\n", "(MAIN)\n"]).unwrap_err();
+
+        assert!(matches!(e.kind, ErrorKind::DuplicateSymbol));
+        assert_eq!(e.text, "MAIN");
+    }
+
+    #[test]
+    fn test_link_conflicting_pins() {
+        let pinned = |name, address| {
+            let mut symbol_table = SymbolTable::new();
+            symbol_table.pin(name, address);
+            compile("@result\n", &symbol_table, false).unwrap()
+        };
+        let exported = compile("(result)\n", &SymbolTable::new(), false).unwrap();
+
+        for other in [pinned("result", 17), exported].iter() {
+            let e = link(&[(None, &pinned("result", 16)), (None, other)]).unwrap_err();
+
+            assert!(matches!(e.kind, ErrorKind::ConflictingPin));
+            assert_eq!(e.text, "result");
+        }
+
+        assert!(link(&[(None, &pinned("result", 16)), (None, &pinned("result", 16))]).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+//! Lint: Warn about valid but suspicious assembly.
+//!
+//! - Jumps with nothing loading A since the start, or since a label that is
+//!   jumped to
+//! - C-instructions using M right after `@LABEL` loads a ROM address
+//! - Labels defined more than once (the last definition wins)
+//! - Labels that are never used
+//! - Variables used only once, which are often misspelt names
+
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{AssemblyError, Warning, WarningKind};
+use crate::instruction::{Address, Instruction, Jump};
+use crate::parser::{self, Command};
+use crate::preprocessor::Preprocessed;
+use crate::symboltable::{self, SymbolTable};
+
+/// Lint the preprocessed `source`, locating warnings in the original files.
+/// Symbols in `symbol_table`, e.g. predefined or pinned, are known to be used.
+pub fn lint(source: &Preprocessed, symbol_table: &SymbolTable) -> Result<Vec<Warning>, AssemblyError> {
+    let commands = parser::parse(&source.source).map_err(|e| source.locate(e))?;
+
+    let mut warnings = Vec::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+
+    for (i, command) in commands.iter().enumerate() {
+        if let Instruction::Label(label) = &command.instruction {
+            if labels.insert(label, i).is_some() {
+                warnings.push(warning(WarningKind::DuplicateLabel, command, 1, &symboltable::readable(label)));
+            }
+        }
+    }
+
+    // Control may arrive at these labels with anything in A
+    let targets: HashSet<&str> = commands.iter()
+        .filter_map(|c| match &c.instruction {
+            Instruction::A(address) => Some(symbols(address)),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+
+    let mut uses: HashMap<&str, Vec<&Command>> = HashMap::new();
+    let mut loaded = false;
+
+    for (i, command) in commands.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|j| &commands[j].instruction);
+
+        match &command.instruction {
+            Instruction::A(address) => {
+                for symbol in symbols(address) {
+                    uses.entry(symbol).or_default().push(command);
+                }
+
+                loaded = true;
+            },
+            Instruction::C { dest, jump, .. } => {
+                if *jump != Jump::Null && !loaded {
+                    warnings.push(warning(WarningKind::JumpWithoutTarget, command, 0, &command.instruction.to_string()));
+                }
+
+                // A computed address, e.g. `A=M`, may be a target
+                if dest.bits() & 0b100 != 0 {
+                    loaded = true;
+                }
+
+                if let Some(Instruction::A(address)) = previous {
+                    let at_label = symbols(address).any(|s| labels.contains_key(s));
+
+                    if at_label && command.instruction.accesses_memory() {
+                        warnings.push(warning(WarningKind::MemoryAtLabel, command, 0, &command.instruction.to_string()));
+                    }
+                }
+            },
+            Instruction::Label(label) => {
+                if targets.contains(label.as_str()) {
+                    loaded = false;
+                }
+            },
+        }
+    }
+
+    for (label, i) in labels.iter() {
+        if !uses.contains_key(label) {
+            warnings.push(warning(WarningKind::UnusedLabel, &commands[*i], 1, &symboltable::readable(label)));
+        }
+    }
+
+    for (symbol, commands) in uses.iter() {
+        let variable = !labels.contains_key(symbol) && !symbol_table.contains(symbol)
+            && !symboltable::is_scoped(symbol);
+
+        if let [command] = commands.as_slice() {
+            if variable {
+                warnings.push(warning(WarningKind::SingleUseVariable, command, 1, symbol));
+            }
+        }
+    }
+
+    warnings.sort_by_key(|w| (w.line, w.column));
+
+    Ok(warnings.into_iter().map(|w| source.locate_warning(w)).collect())
+}
+
+fn symbols(address: &Address) -> Box<dyn Iterator<Item = &str> + '_> {
+    match address {
+        Address::Value(_) => Box::new(std::iter::empty()),
+        Address::Symbol(symbol) => Box::new(std::iter::once(symbol.as_str())),
+        Address::Expr(expr) => Box::new(expr.symbols()),
+    }
+}
+
+// `offset` skips the '@' or '(' before a symbol
+fn warning(kind: WarningKind, command: &Command, offset: usize, text: &str) -> Warning {
+    Warning::new(kind, command.line, command.column + offset, text)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::preprocess;
+
+    fn lint_str(source: &str) -> Vec<(WarningKind, usize, String)> {
+        let source = preprocess(source, None).unwrap();
+
+        lint(&source, &SymbolTable::new()).unwrap().into_iter().map(|w| (w.kind, w.line, w.text)).collect()
+    }
+
+    #[test]
+    fn test_clean_program_has_no_warnings() {
+        let source = "@i\nM=0\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n";
+
+        assert_eq!(lint_str(source), vec!());
+    }
+
+    #[test]
+    fn test_jump_without_target() {
+        assert_eq!(lint_str("D=M\nD;JGT\n(END)\n@END\n0;JMP\n"), vec!(
+            (WarningKind::JumpWithoutTarget, 2, String::from("D;JGT")),
+        ));
+    }
+
+    #[test]
+    fn test_jump_after_label_has_no_target() {
+        assert_eq!(lint_str("(END)\n0;JMP\n@END\n"), vec!(
+            (WarningKind::JumpWithoutTarget, 2, String::from("0;JMP")),
+        ));
+    }
+
+    #[test]
+    fn test_jump_after_computing_with_target_loaded() {
+        assert_eq!(lint_str("(L)\n@L\nD=D-1\nD;JGT\n"), vec!());
+    }
+
+    #[test]
+    fn test_jump_after_label_not_jumped_to() {
+        assert_eq!(lint_str("(L)\n@END\n(SKIP)\nD;JEQ\n(END)\n@L\n0;JMP\n"), vec!(
+            (WarningKind::UnusedLabel, 3, String::from("SKIP")),
+        ));
+    }
+
+    #[test]
+    fn test_jump_to_computed_address() {
+        assert_eq!(lint_str("@R14\nA=M\n0;JMP\n"), vec!());
+    }
+
+    #[test]
+    fn test_memory_at_label() {
+        assert_eq!(lint_str("(DATA)\n@DATA+1\nD=M\n"), vec!(
+            (WarningKind::MemoryAtLabel, 3, String::from("D=M")),
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        assert_eq!(lint_str("(END)\n@END\n(END)\n"), vec!(
+            (WarningKind::DuplicateLabel, 3, String::from("END")),
+        ));
+    }
+
+    #[test]
+    fn test_unused_label() {
+        assert_eq!(lint_str("(START)\n@1\n"), vec!(
+            (WarningKind::UnusedLabel, 1, String::from("START")),
+        ));
+    }
+
+    #[test]
+    fn test_single_use_variable() {
+        assert_eq!(lint_str("@count\nM=0\n@cuont\nM=M+1\n@count\n@R0\n"), vec!(
+            (WarningKind::SingleUseVariable, 3, String::from("cuont")),
+        ));
+    }
+
+    #[test]
+    fn test_warnings_are_located_in_macro_definitions() {
+        let source = ".macro SKIP\n(NEXT)\n.endm\n@1\nSKIP\n";
+        let warnings = lint(&preprocess(source, None).unwrap(), &SymbolTable::new()).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line, warnings[0].expansions[0].1.line), (2, 5));
+    }
+}
@@ -0,0 +1,127 @@
+//! Listing: Annotate assembly source with ROM addresses and machine words.
+
+use std::io::{prelude::*, BufWriter};
+
+use crate::error::AssemblyError;
+use crate::preprocessor::Preprocessed;
+
+struct Entry {
+    line: usize,
+    address: usize,
+    word: Option<u16>,
+    local: Option<String>,
+}
+
+pub struct Listing {
+    entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Listing { entries: Vec::new() }
+    }
+
+    /// Record the command on source `line`, at ROM `address`. Labels have
+    /// no word and are listed at the address they resolve to.
+    pub fn add(&mut self, line: usize, address: usize, word: Option<u16>) {
+        self.entries.push(Entry { line, address, word, local: None });
+    }
+
+    /// Record a local label, listed with its readable scoped `name`.
+    pub fn add_local(&mut self, line: usize, address: usize, name: String) {
+        self.entries.push(Entry { line, address, word: None, local: Some(name) });
+    }
+
+    /// Write every line of `source`, prefixed by its address and word.
+    /// Lines are numbered as they were before preprocessing, with the file
+    /// of those included, and the expansion site of those from macros.
+    pub fn write(&self, source: &Preprocessed, dst: impl Write) -> Result<(), AssemblyError> {
+        let mut writer = BufWriter::new(dst);
+        let mut entries = self.entries.iter().peekable();
+
+        writeln!(&mut writer, "ROM   BINARY            HEX    LINE  SOURCE")?;
+
+        for (i, (location, expansion, text)) in source.lines().enumerate() {
+            let line = location.to_string();
+            let mut text = text.trim_end().to_string();
+
+            if let Some((name, site)) = expansion {
+                text.push_str(&format!("  (in {} at {})", name, site));
+            }
+
+            write_row(&mut writer, entries.next_if(|e| e.line == i + 1), &line, &text)?;
+
+            // Further words of the line, e.g. folded by the optimizer
+            while let Some(entry) = entries.next_if(|e| e.line == i + 1) {
+                write_row(&mut writer, Some(entry), &line, "")?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+fn write_row(writer: &mut impl Write, entry: Option<&Entry>, line: &str, text: &str) -> Result<(), AssemblyError> {
+    match entry {
+        Some(Entry { address, word: Some(word), .. }) => {
+            writeln!(writer, "{:04X}  {:016b}  {:04X}  {:>5}  {}", address, word, word, line, text)?
+        },
+        Some(Entry { address, word: None, local: Some(name), .. }) => {
+            writeln!(writer, "{:04X}  {:16}  {:4}  {:>5}  {}  = {}", address, "", "", line, text, name)?
+        },
+        Some(Entry { address, word: None, .. }) => {
+            writeln!(writer, "{:04X}  {:16}  {:4}  {:>5}  {}", address, "", "", line, text)?
+        },
+        None => {
+            writeln!(writer, "{:4}  {:16}  {:4}  {:>5}  {}", "", "", "", line, text)?
+        },
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::preprocess;
+
+    #[test]
+    fn test_write_listing() {
+        let source = preprocess("// Loop\n(LOOP)\n    @LOOP\n    0;JMP // forever\n", None).unwrap();
+        let mut listing = Listing::new();
+        listing.add(2, 0, None);
+        listing.add(3, 0, Some(0x0000));
+        listing.add(4, 1, Some(0xea87));
+
+        let mut lst = Vec::new();
+        listing.write(&source, &mut lst).unwrap();
+
+        assert_eq!(std::str::from_utf8(&lst).unwrap(), "\
+ROM   BINARY            HEX    LINE  SOURCE
+                                  1  // Loop
+0000                              2  (LOOP)
+0000  0000000000000000  0000      3      @LOOP
+0001  1110101010000111  EA87      4      0;JMP // forever
+");
+    }
+
+    #[test]
+    fn test_write_listing_of_macro() {
+        let source = preprocess(".macro INC\nM=M+1\n.endm\n@i\nINC\n", None).unwrap();
+        let mut listing = Listing::new();
+        listing.add(1, 0, Some(0x0010));
+        listing.add(2, 1, Some(0xfdc8));
+
+        let mut lst = Vec::new();
+        listing.write(&source, &mut lst).unwrap();
+
+        assert_eq!(std::str::from_utf8(&lst).unwrap(), "\
+ROM   BINARY            HEX    LINE  SOURCE
+0000  0000000000010000  0010      4  @i
+0001  1111110111001000  FDC8      2  M=M+1  (in INC at 5)
+");
+    }
+}
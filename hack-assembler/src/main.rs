@@ -1,23 +1,95 @@
-/// Executable for assembling .hack binaries from .asm assembly.
-///
-/// Usage: hack-assembler <file.asm>
+//! Executable for assembling .hack binaries from .asm assembly.
+//!
+//! Usage: hack-assembler [options] <file.asm>
 
 use std::env;
+use std::fs;
 use std::path::Path;
 
-use assembler;
+use assembler::instruction::Address;
+use assembler::{Format, SymbolFormat};
 
-fn main() -> Result<(), std::io::Error> {
-    let args: Vec<String> = env::args().collect();
+const USAGE: &str = "\
+Usage: hack-assembler [options] <file.asm>
 
-    // Handle args.len(0). Ignore args[2..]
-    let arg = args.get(1).unwrap_or_else(|| {
-        println!("Usage: hack-assembler <file.asm>");
-        std::process::exit(1);
-    });
+Options:
+    -c, --compile        Write a relocatable <file.o> object for hack-link
+    -D, --define NAME=VALUE
+                         Define a symbol, alongside the predefined symbols
+    -f, --format=FORMAT  Binary format: hack (default), bin, ihex, memb, memh or logisim
+    -l, --listing        Also write a <file.lst> listing
+    -O, --optimize       Shrink the program with peephole optimizations, unless
+                         it jumps to numeric addresses
+    -W, --lint           Warn about suspicious but valid assembly
+    -s, --symbols[=json] Also write a <file.sym> symbol map, as text or JSON
+    --variables=FILE     Pin the variables in a NAME ADDRESS map to fixed RAM addresses";
+
+fn main() {
+    let mut options = assembler::Options::default();
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" | "--format" => options.format = format(&args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--format=") => options.format = format(&arg["--format=".len()..]),
+            "-c" | "--compile" => options.object = true,
+            "-D" | "--define" => options.defines.push(define(&args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with("--define=") => options.defines.push(define(&arg["--define=".len()..])),
+            _ if arg.starts_with("--variables=") => options.variables.extend(variables(&arg["--variables=".len()..])),
+            "-l" | "--listing" => options.listing = true,
+            "-O" | "--optimize" => options.optimize = true,
+            "-W" | "--lint" => options.lint = true,
+            "-s" | "--symbols" | "--symbols=text" => options.symbols = Some(SymbolFormat::Text),
+            "--symbols=json" => options.symbols = Some(SymbolFormat::Json),
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
 
-    let path_asm = Path::new(arg);
-    let path_hack = path_asm.with_extension("hack");
+    let path = path.unwrap_or_else(|| usage());
+    let path_asm = Path::new(&path);
+    let extension = if options.object { "o" } else { options.format.extension() };
+    let path_hack = path_asm.with_extension(extension);
+
+    match assembler::assemble_with(path_asm, &path_hack, &options) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("{}", warning);
+            }
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn format(name: &str) -> Format {
+    name.parse().unwrap_or_else(|_| usage())
+}
+
+// NAME=VALUE, where VALUE is a constant such as 16 or 0x4000
+fn define(definition: &str) -> (String, u16) {
+    let (name, value) = definition.split_once('=').unwrap_or_else(|| usage());
+
+    match (name.parse(), value.parse()) {
+        (Ok(Address::Symbol(name)), Ok(Address::Value(value))) => (name, value),
+        _ => usage(),
+    }
+}
+
+fn variables(path: &str) -> Vec<(String, u16)> {
+    let map = fs::read_to_string(path).map_err(assembler::AssemblyError::io);
+
+    map.and_then(|map| assembler::parse_variables(&map)).unwrap_or_else(|e| {
+        eprintln!("{}", e.in_file(path));
+        std::process::exit(1);
+    })
+}
 
-    assembler::assemble(path_asm, &path_hack)
+fn usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(1);
 }
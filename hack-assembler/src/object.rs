@@ -0,0 +1,379 @@
+//! Object: Relocatable object files, for separate compilation.
+//!
+//! ```text
+//! HACK OBJECT
+//! code 4
+//! 0002
+//! ea87
+//! 0000
+//! ea87
+//! export MAIN 0
+//! import Sys.init
+//! pin result 256
+//! reloc 0
+//! reloc 2 Sys.init
+//! ```
+//!
+//! Code is assembled as if loaded at ROM address 0. Each relocation adds
+//! the object's base address, or the address of an imported symbol, to a
+//! word. Imports that no object exports are variables, allocated when the
+//! objects are linked unless pinned to a fixed address.
+
+use std::collections::HashMap;
+use std::io::{prelude::*, BufWriter};
+use std::str::FromStr;
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::expression::{Op, Term};
+use crate::instruction::{Address, Instruction};
+use crate::optimizer;
+use crate::parser;
+use crate::symboltable::{self, Kind, SymbolTable};
+use crate::{MAX_CONSTANT, ROM_SIZE};
+
+const MAGIC: &str = "HACK OBJECT";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    pub code: Vec<u16>,
+    /// Global labels, by address within `code`.
+    pub exports: Vec<(String, u16)>,
+    /// Symbols not defined by the object.
+    pub imports: Vec<String>,
+    /// Variables pinned to fixed RAM addresses.
+    pub pins: Vec<(String, u16)>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// Address of the word within `code`.
+    pub address: u16,
+    /// Index of the import whose address is added to the word, or `None`
+    /// to add the object's base address.
+    pub import: Option<usize>,
+}
+
+// What an A-instruction's value is relative to
+enum Base {
+    Absolute,
+    Object,
+    Import(String),
+}
+
+/// Assemble preprocessed `source` into an object, leaving labels relative
+/// to the object and symbols it doesn't define unresolved. Variables in
+/// `symbol_table` are pinned; other symbols in it are constants.
+pub fn compile(source: &str, symbol_table: &SymbolTable, optimize: bool) -> Result<Object, AssemblyError> {
+    let mut commands = parser::parse(source)?;
+
+    if optimize {
+        commands = optimizer::optimize(commands);
+    }
+    let mut labels = HashMap::new();
+
+    // First Pass
+    let mut rom_address = 0;
+
+    for command in commands.iter() {
+        match &command.instruction {
+            Instruction::Label(label) => {
+                if let Some((_, Kind::Variable | Kind::Defined)) = symbol_table.hash.get(label) {
+                    return Err(AssemblyError::new(ErrorKind::LabelConflict, command.line, command.column + 1, label))
+                }
+
+                labels.insert(label.as_str(), rom_address);
+            },
+            _ if rom_address as usize == ROM_SIZE => {
+                let text = command.instruction.to_string();
+                return Err(AssemblyError::new(ErrorKind::ProgramTooLarge, command.line, command.column, &text))
+            },
+            _ => rom_address += 1,
+        }
+    }
+
+    let mut exports: Vec<(String, u16)> = labels.iter()
+        .filter(|(label, _)| !symboltable::is_scoped(label))
+        .map(|(label, address)| (label.to_string(), *address))
+        .collect();
+    exports.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    let pins = symbol_table.entries().into_iter()
+        .filter(|(_, _, kind)| *kind == Kind::Variable)
+        .map(|(name, address, _)| (name.to_string(), address))
+        .collect();
+
+    let mut object = Object { exports, pins, ..Default::default() };
+
+    // Second Pass
+    for command in commands.iter() {
+        let word = match &command.instruction {
+            Instruction::A(address) => {
+                let error = |kind, text: &str| AssemblyError::new(kind, command.line, command.column + 1, text);
+                let (value, base) = split(address, &labels, symbol_table).map_err(|(kind, text)| error(kind, &text))?;
+
+                let import = match base {
+                    Base::Absolute if value < 0 => return Err(error(ErrorKind::Overflow, &address.to_string())),
+                    Base::Absolute if value > MAX_CONSTANT as i32 => {
+                        return Err(error(ErrorKind::ConstantTooLarge, &address.to_string()))
+                    },
+                    Base::Absolute => None,
+                    Base::Object => Some(None),
+                    Base::Import(symbol) => Some(Some(object.import(symbol))),
+                };
+
+                if let Some(import) = import {
+                    object.relocations.push(Relocation { address: object.code.len() as u16, import });
+                }
+
+                Some(value as u16)
+            },
+            instruction => instruction.encode(),
+        };
+
+        if let Some(word) = word {
+            object.code.push(word);
+        }
+    }
+
+    Ok(object)
+}
+
+impl Object {
+    fn import(&mut self, symbol: String) -> usize {
+        match self.imports.iter().position(|s| *s == symbol) {
+            Some(i) => i,
+            None => {
+                self.imports.push(symbol);
+                self.imports.len() - 1
+            },
+        }
+    }
+
+    pub fn write(&self, dst: impl Write) -> Result<(), AssemblyError> {
+        let mut writer = BufWriter::new(dst);
+
+        writeln!(&mut writer, "{}", MAGIC)?;
+        writeln!(&mut writer, "code {}", self.code.len())?;
+
+        for word in self.code.iter() {
+            writeln!(&mut writer, "{:04x}", word)?;
+        }
+
+        for (name, address) in self.exports.iter() {
+            writeln!(&mut writer, "export {} {}", name, address)?;
+        }
+
+        for name in self.imports.iter() {
+            writeln!(&mut writer, "import {}", name)?;
+        }
+
+        for (name, address) in self.pins.iter() {
+            writeln!(&mut writer, "pin {} {}", name, address)?;
+        }
+
+        for relocation in self.relocations.iter() {
+            match relocation.import {
+                Some(i) => writeln!(&mut writer, "reloc {} {}", relocation.address, self.imports[i])?,
+                None => writeln!(&mut writer, "reloc {}", relocation.address)?,
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// Split an address into a value (wrapping to u16), and at most one label or
+// import it is relative to. Errors carry the offending text.
+fn split(address: &Address, labels: &HashMap<&str, u16>,
+         symbol_table: &SymbolTable) -> Result<(i32, Base), (ErrorKind, String)> {
+    let symbol;
+    let terms: Vec<(Op, &Term)> = match address {
+        Address::Value(value) => return Ok((*value as i32, Base::Absolute)),
+        Address::Symbol(s) => {
+            symbol = Term::Symbol(s.clone());
+            vec!((Op::Add, &symbol))
+        },
+        Address::Expr(expr) => expr.terms().collect(),
+    };
+
+    let not_relocatable = || (ErrorKind::NotRelocatable, symboltable::readable(&address.to_string()));
+    let mut value = 0;
+    let mut bases = 0;
+    let mut import = None;
+
+    for (op, term) in terms {
+        let sign = match op {
+            Op::Add => 1,
+            Op::Sub => -1,
+        };
+
+        match term {
+            Term::Value(v) => value += sign * v,
+            Term::Symbol(s) => {
+                if let Some(address) = labels.get(s.as_str()) {
+                    value += sign * *address as i32;
+                    bases += sign;
+                } else if let Some((address, kind)) = symbol_table.hash.get(s) {
+                    match kind {
+                        Kind::Variable if sign == 1 && import.is_none() => import = Some(s.clone()),
+                        Kind::Variable => return Err(not_relocatable()),
+                        _ => value += sign * *address as i32,
+                    }
+                } else if symboltable::is_scoped(s) {
+                    return Err((ErrorKind::UndefinedSymbol, symboltable::unscoped(s).to_string()))
+                } else if sign == 1 && import.is_none() {
+                    import = Some(s.clone());
+                } else {
+                    return Err(not_relocatable())
+                }
+            },
+        }
+    }
+
+    match (bases, import) {
+        (0, None) => Ok((value, Base::Absolute)),
+        (1, None) => Ok((value, Base::Object)),
+        (0, Some(symbol)) => Ok((value, Base::Import(symbol))),
+        _ => Err(not_relocatable()),
+    }
+}
+
+impl FromStr for Object {
+    type Err = AssemblyError;
+
+    /// Read an object file. Errors are located at the offending line.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = |i: usize, line: &str| AssemblyError::new(ErrorKind::InvalidObject, i + 1, 1, line.trim());
+        let mut lines = text.lines().enumerate();
+        let mut object = Object::default();
+
+        match lines.next() {
+            Some((_, MAGIC)) => (),
+            other => return Err(error(0, other.map_or("", |(_, line)| line))),
+        }
+
+        while let Some((i, line)) = lines.next() {
+            let number = |n: &str| n.parse::<u16>().map_err(|_| error(i, line));
+
+            match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                ["code", n] => {
+                    for _ in 0..number(n)? {
+                        let (j, word) = lines.next().ok_or_else(|| error(i, line))?;
+                        object.code.push(u16::from_str_radix(word.trim(), 16).map_err(|_| error(j, word))?);
+                    }
+                },
+                ["export", name, address] => object.exports.push((name.to_string(), number(address)?)),
+                ["import", name] => object.imports.push(name.to_string()),
+                ["pin", name, address] => object.pins.push((name.to_string(), number(address)?)),
+                ["reloc", address, name @ ..] if name.len() < 2 => {
+                    let address = number(address)?;
+                    let import = match name.first() {
+                        Some(name) => Some(object.imports.iter().position(|s| s == name).ok_or_else(|| error(i, line))?),
+                        None => None,
+                    };
+
+                    if address as usize >= object.code.len() {
+                        return Err(error(i, line))
+                    }
+
+                    object.relocations.push(Relocation { address, import });
+                },
+                [] => (),
+                _ => return Err(error(i, line)),
+            }
+        }
+
+        Ok(object)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_str(source: &str) -> Object {
+        compile(source, &SymbolTable::new(), false).unwrap()
+    }
+
+    fn compile_err(source: &str) -> AssemblyError {
+        compile(source, &SymbolTable::new(), false).unwrap_err()
+    }
+
+    #[test]
+    fn test_compile_relocations() {
+        let object = compile_str("(MAIN)\n@MAIN\n0;JMP\n@SCREEN+1\n@Sys.init-1\n@count\n@END-MAIN\n(END)\n");
+
+        assert_eq!(object.code, vec!(0, 0xea87, 0x4001, 0xffff, 0, 6));
+        assert_eq!(object.exports, vec!((String::from("MAIN"), 0), (String::from("END"), 6)));
+        assert_eq!(object.imports, vec!(String::from("Sys.init"), String::from("count")));
+        assert_eq!(object.relocations, vec!(
+            Relocation { address: 0, import: None },
+            Relocation { address: 3, import: Some(0) },
+            Relocation { address: 4, import: Some(1) },
+        ));
+    }
+
+    #[test]
+    fn test_compile_does_not_export_local_labels() {
+        let object = compile_str("(MAIN)\n(.loop)\n@.loop\n0;JMP\n");
+
+        assert_eq!(object.exports, vec!((String::from("MAIN"), 0)));
+        assert_eq!(object.relocations, vec!(Relocation { address: 0, import: None }));
+    }
+
+    #[test]
+    fn test_compile_not_relocatable() {
+        let e = compile_err("@a+b\n");
+        assert!(matches!(e.kind, ErrorKind::NotRelocatable));
+        assert_eq!((e.column, e.text.as_str()), (2, "a+b"));
+
+        assert!(matches!(compile_err("(L)\n@L+L\n").kind, ErrorKind::NotRelocatable));
+        assert!(matches!(compile_err("@0-x\n").kind, ErrorKind::NotRelocatable));
+    }
+
+    #[test]
+    fn test_compile_pinned_variables_and_defines() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.pin("result", 256);
+        symbol_table.addEntry("DEBUG", 1, Kind::Defined);
+
+        let object = compile("@result\n@DEBUG\n", &symbol_table, false).unwrap();
+
+        assert_eq!(object.code, vec!(0, 1));
+        assert_eq!(object.imports, vec!(String::from("result")));
+        assert_eq!(object.pins, vec!((String::from("result"), 256)));
+
+        let e = compile("(DEBUG)\n", &symbol_table, false).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::LabelConflict));
+        assert_eq!((e.column, e.text.as_str()), (2, "DEBUG"));
+    }
+
+    #[test]
+    fn test_write_read_round_trip() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.pin("result", 256);
+
+        let object = compile("(MAIN)\n@MAIN\n0;JMP\n@Sys.init\n@count\n", &symbol_table, false).unwrap();
+        let mut text = Vec::new();
+        object.write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        assert!(text.starts_with("HACK OBJECT\ncode 4\n0000\nea87\n"));
+        assert_eq!(text.parse::<Object>().unwrap(), object);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let line = |text: &str| text.parse::<Object>().err().map(|e| e.line);
+
+        assert_eq!(line("HACK\n"), Some(1));
+        assert_eq!(line("HACK OBJECT\ncode 2\n0000\n"), Some(2));
+        assert_eq!(line("HACK OBJECT\ncode 1\nxyz\n"), Some(3));
+        assert_eq!(line("HACK OBJECT\ncode 1\n0000\nreloc 1\n"), Some(4));
+        assert_eq!(line("HACK OBJECT\nreloc 0 missing\n"), Some(2));
+    }
+}
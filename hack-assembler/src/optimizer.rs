@@ -0,0 +1,210 @@
+//! Optimizer: Peephole optimizations over parsed instructions.
+//!
+//! - Fold stack pointer updates, e.g. push D
+//!   `@SP, A=M, M=D, @SP, M=M+1` to `@SP, AM=M+1, A=A-1, M=D`
+//!   and pop `@SP, M=M-1, A=M` to `@SP, AM=M-1`
+//! - Drop no-ops, such as `D=D` or a comp with no dest and no jump
+//! - Remove code after an unconditional jump, up to the next label
+//! - Remove `@X` reloads when A already holds X
+//!
+//! Labels are always kept. A program with expressions that refer to labels,
+//! e.g. `@LOOP+2`, or that jumps to a numeric address, e.g. `@5, 0;JMP`,
+//! depends on the distance between instructions, so it is left unchanged.
+//! Other uses of a number as a ROM address, e.g. a return address stored in
+//! RAM, cannot be told from data and are not safe to optimize.
+
+use std::collections::HashSet;
+
+use crate::instruction::{Address, Comp, Dest, Instruction, Jump};
+use crate::parser::Command;
+
+pub fn optimize(commands: Vec<Command>) -> Vec<Command> {
+    if offsets_labels(&commands) || jumps_to_numbers(&commands) {
+        return commands
+    }
+
+    let mut commands = commands;
+
+    // Each pass only removes instructions, so this terminates
+    loop {
+        let length = commands.len();
+
+        commands = fold_stack(commands);
+        commands.retain(|c| !is_noop(&c.instruction));
+        remove_unreachable(&mut commands);
+        remove_reloads(&mut commands);
+
+        if commands.len() == length {
+            return commands
+        }
+    }
+}
+
+// Do any expressions add offsets to labels?
+fn offsets_labels(commands: &[Command]) -> bool {
+    let labels: HashSet<&str> = commands.iter().filter_map(|c| match &c.instruction {
+        Instruction::Label(label) => Some(label.as_str()),
+        _ => None,
+    }).collect();
+
+    commands.iter().any(|c| match &c.instruction {
+        Instruction::A(Address::Expr(expr)) => expr.symbols().any(|s| labels.contains(s)),
+        _ => false,
+    })
+}
+
+// Do any jumps follow a numeric A-instruction?
+fn jumps_to_numbers(commands: &[Command]) -> bool {
+    let instructions: Vec<&Instruction> = commands.iter()
+        .map(|c| &c.instruction)
+        .filter(|i| !matches!(i, Instruction::Label(_)))
+        .collect();
+
+    instructions.windows(2).any(|pair| match pair {
+        [Instruction::A(Address::Value(_)), Instruction::C { jump, .. }] => *jump != Jump::Null,
+        _ => false,
+    })
+}
+
+fn c(dest: Dest, comp: Comp) -> Instruction {
+    Instruction::C { dest, comp, jump: Jump::Null }
+}
+
+fn fold_stack(commands: Vec<Command>) -> Vec<Command> {
+    let sp = Instruction::A(Address::Symbol(String::from("SP")));
+    let push = [sp.clone(), c(Dest::A, Comp::M), c(Dest::M, Comp::D), sp.clone(), c(Dest::M, Comp::MPlusOne)];
+    let pop = [sp.clone(), c(Dest::M, Comp::MMinusOne), c(Dest::A, Comp::M)];
+
+    let starts_with = |commands: &[Command], pattern: &[Instruction]| {
+        commands.len() >= pattern.len() && commands.iter().zip(pattern).all(|(c, i)| c.instruction == *i)
+    };
+
+    let mut folded = Vec::with_capacity(commands.len());
+    let mut i = 0;
+
+    while i < commands.len() {
+        let rest = &commands[i..];
+        let at = |instruction| Command { instruction, line: rest[0].line, column: rest[0].column };
+
+        // Push leaves A at the new top of stack, not SP, so must be followed
+        // by an instruction that loads A
+        let loads_a = matches!(rest.get(push.len()), Some(Command { instruction: Instruction::A(_), .. }) | None);
+
+        if starts_with(rest, &push) && loads_a {
+            folded.push(at(sp.clone()));
+            folded.push(at(c(Dest::AM, Comp::MPlusOne)));
+            folded.push(at(c(Dest::A, Comp::AMinusOne)));
+            folded.push(at(c(Dest::M, Comp::D)));
+            i += push.len();
+        } else if starts_with(rest, &pop) {
+            folded.push(at(sp.clone()));
+            folded.push(at(c(Dest::AM, Comp::MMinusOne)));
+            i += pop.len();
+        } else {
+            folded.push(commands[i].clone());
+            i += 1;
+        }
+    }
+
+    folded
+}
+
+fn is_noop(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::C { dest, comp, jump: Jump::Null } => {
+            matches!((dest, comp), (Dest::Null, _) | (Dest::D, Comp::D) | (Dest::A, Comp::A) | (Dest::M, Comp::M))
+        },
+        _ => false,
+    }
+}
+
+fn remove_unreachable(commands: &mut Vec<Command>) {
+    let mut reachable = true;
+
+    commands.retain(|c| match &c.instruction {
+        Instruction::Label(_) => {
+            reachable = true;
+            true
+        },
+        _ if !reachable => false,
+        Instruction::C { jump: Jump::JMP, .. } => {
+            reachable = false;
+            true
+        },
+        _ => true,
+    });
+}
+
+fn remove_reloads(commands: &mut Vec<Command>) {
+    let mut a: Option<Address> = None;
+
+    commands.retain(|c| match &c.instruction {
+        Instruction::A(address) if a.as_ref() == Some(address) => false,
+        Instruction::A(address) => {
+            a = Some(address.clone());
+            true
+        },
+        Instruction::C { dest, .. } if dest.bits() & 0b100 != 0 => {
+            a = None;
+            true
+        },
+        Instruction::C { .. } => true,
+        Instruction::Label(_) => {
+            a = None;
+            true
+        },
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn optimize_str(source: &str) -> String {
+        let commands = optimize(parse(source).unwrap());
+
+        commands.iter().map(|c| format!("{}\n", c.instruction)).collect()
+    }
+
+    #[test]
+    fn test_fold_push() {
+        assert_eq!(optimize_str("@SP\nA=M\nM=D\n@SP\nM=M+1\n@5\n"), "@SP\nAM=M+1\nA=A-1\nM=D\n@5\n");
+    }
+
+    #[test]
+    fn test_fold_push_needs_a_reload_after() {
+        assert_eq!(optimize_str("@SP\nA=M\nM=D\n@SP\nM=M+1\nD=A\n"), "@SP\nA=M\nM=D\n@SP\nM=M+1\nD=A\n");
+    }
+
+    #[test]
+    fn test_fold_pop() {
+        assert_eq!(optimize_str("@SP\nM=M-1\nA=M\nD=M\n"), "@SP\nAM=M-1\nD=M\n");
+    }
+
+    #[test]
+    fn test_drop_noops() {
+        assert_eq!(optimize_str("D=D\nA=A\nM=M\nD\nD=M\nD;JGT\n"), "D=M\nD;JGT\n");
+    }
+
+    #[test]
+    fn test_remove_unreachable_keeps_labels() {
+        assert_eq!(optimize_str("@END\n0;JMP\nD=M\n@1\n(END)\n@END\n0;JMP\n"), "@END\n0;JMP\n(END)\n@END\n0;JMP\n");
+    }
+
+    #[test]
+    fn test_remove_reloads() {
+        assert_eq!(optimize_str("@i\nM=0\n@i\nD=M\n@i\nA=M\n@i\n(L)\n@i\n"), "@i\nM=0\nD=M\nA=M\n@i\n(L)\n@i\n");
+    }
+
+    #[test]
+    fn test_label_offsets_are_left_unchanged() {
+        assert_eq!(optimize_str("(L)\n@L+2\nD=D\n"), "(L)\n@L+2\nD=D\n");
+    }
+
+    #[test]
+    fn test_numeric_jumps_are_left_unchanged() {
+        assert_eq!(optimize_str("@3\nD;JGT\nD=D\n@0\n0;JMP\n"), "@3\nD;JGT\nD=D\n@0\n0;JMP\n");
+    }
+}
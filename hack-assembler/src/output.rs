@@ -0,0 +1,203 @@
+//! Output: Encode assembled machine words for loaders, simulators and FPGAs.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{prelude::*, BufWriter};
+use std::str::FromStr;
+
+use crate::error::{AssemblyError, ErrorKind};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Lines of 16 '0'/'1' characters, as read by the nand2tetris tools.
+    #[default]
+    Hack,
+    /// Raw big-endian 16-bit words.
+    Binary,
+    /// Intel HEX records, two bytes per word (big-endian).
+    IntelHex,
+    /// Verilog `$readmemb` image, one binary word per line.
+    ReadMemB,
+    /// Verilog `$readmemh` image, one hex word per line.
+    ReadMemH,
+    /// Logisim "v2.0 raw" memory image.
+    Logisim,
+}
+
+impl Format {
+    /// Conventional file extension for the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Hack => "hack",
+            Format::Binary => "bin",
+            Format::IntelHex => "hex",
+            Format::ReadMemB | Format::ReadMemH => "mem",
+            Format::Logisim => "img",
+        }
+    }
+}
+
+static FORMATS: &[(Format, &str)] = &[
+    (Format::Hack,     "hack"),
+    (Format::Binary,   "bin"),
+    (Format::IntelHex, "ihex"),
+    (Format::ReadMemB, "memb"),
+    (Format::ReadMemH, "memh"),
+    (Format::Logisim,  "logisim"),
+];
+
+#[derive(Debug)]
+pub struct ParseFormatError;
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        FORMATS.iter()
+               .find(|(_, n)| *n == name)
+               .map(|(f, _)| *f)
+               .ok_or(ParseFormatError)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = FORMATS.iter().find(|(t, _)| t == self).unwrap().1;
+        write!(f, "{}", name)
+    }
+}
+
+/// Write machine words to `dst` in the given format.
+pub fn write_words(words: &[u16], format: Format, dst: impl Write) -> Result<(), AssemblyError> {
+    let mut writer = BufWriter::new(dst);
+
+    match format {
+        Format::Hack | Format::ReadMemB => {
+            for word in words {
+                writeln!(&mut writer, "{:0>16b}", word)?;
+            }
+        },
+        Format::Binary => {
+            for word in words {
+                writer.write_all(&word.to_be_bytes())?;
+            }
+        },
+        Format::IntelHex => {
+            let mut segment = 0;
+
+            for (i, chunk) in words.chunks(8).enumerate() {
+                let address = i * 16;
+
+                // Records address 64K bytes, past which an extended linear
+                // address record gives the upper 16 bits
+                if address >> 16 != segment {
+                    segment = address >> 16;
+                    let upper = u16::try_from(segment)
+                        .map_err(|_| AssemblyError::new(ErrorKind::ProgramTooLarge, 0, 0, ""))?;
+                    writeln!(&mut writer, "{}", ihex_record(0, 0x04, &upper.to_be_bytes()))?;
+                }
+
+                let bytes: Vec<u8> = chunk.iter().flat_map(|w| w.to_be_bytes()).collect();
+                writeln!(&mut writer, "{}", ihex_record(address as u16, 0x00, &bytes))?;
+            }
+
+            writeln!(&mut writer, "{}", ihex_record(0, 0x01, &[]))?;
+        },
+        Format::ReadMemH => {
+            for word in words {
+                writeln!(&mut writer, "{:04x}", word)?;
+            }
+        },
+        Format::Logisim => {
+            writeln!(&mut writer, "v2.0 raw")?;
+
+            for line in words.chunks(8) {
+                let line: Vec<String> = line.iter().map(|w| format!("{:x}", w)).collect();
+                writeln!(&mut writer, "{}", line.join(" "))?;
+            }
+        },
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// :LLAAAATT<data>CC where CC is the two's complement of the byte sum
+fn ihex_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let [hi, lo] = address.to_be_bytes();
+    let mut bytes = vec!(data.len() as u8, hi, lo, record_type);
+    bytes.extend_from_slice(data);
+
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}", hex)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: [u16; 3] = [0x0002, 0xec10, 0xea87];
+
+    fn write_str(words: &[u16], format: Format) -> String {
+        let mut out = Vec::new();
+        write_words(words, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_binary_is_big_endian() {
+        let mut out = Vec::new();
+        write_words(&WORDS, Format::Binary, &mut out).unwrap();
+
+        assert_eq!(out, vec!(0x00, 0x02, 0xec, 0x10, 0xea, 0x87));
+    }
+
+    #[test]
+    fn test_write_intel_hex() {
+        assert_eq!(write_str(&WORDS, Format::IntelHex), "\
+:060000000002EC10EA878B
+:00000001FF
+");
+    }
+
+    #[test]
+    fn test_write_intel_hex_record_addresses() {
+        let hex = write_str(&[0; 9], Format::IntelHex);
+        let lines: Vec<&str> = hex.lines().collect();
+
+        assert_eq!(lines[0], ":1000000000000000000000000000000000000000F0");
+        assert_eq!(lines[1], ":020010000000EE");
+    }
+
+    #[test]
+    fn test_write_intel_hex_past_64k() {
+        let hex = write_str(&[0; 32 * 1024 + 1], Format::IntelHex);
+        let lines: Vec<&str> = hex.lines().collect();
+
+        assert_eq!(lines[4095], ":10FFF0000000000000000000000000000000000001");
+        assert_eq!(lines[4096..], [":020000040001F9", ":020000000000FE", ":00000001FF"]);
+    }
+
+    #[test]
+    fn test_write_readmemh() {
+        assert_eq!(write_str(&WORDS, Format::ReadMemH), "0002\nec10\nea87\n");
+    }
+
+    #[test]
+    fn test_write_logisim() {
+        assert_eq!(write_str(&WORDS, Format::Logisim), "v2.0 raw\n2 ec10 ea87\n");
+    }
+
+    #[test]
+    fn test_format_names_round_trip() {
+        for (format, name) in FORMATS {
+            assert_eq!(name.parse::<Format>().unwrap(), *format);
+            assert_eq!(format.to_string(), *name);
+        }
+    }
+}
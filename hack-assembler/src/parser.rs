@@ -1,20 +1,27 @@
-/// Parser: Encapsulate access to input code.
+//! Parser: Encapsulate access to input code.
 
-use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
-pub struct Parser {
-    reader: BufReader<File>,
+use crate::error::AssemblyError;
+use crate::instruction::{Address, Instruction};
+use crate::symboltable;
+
+pub struct Parser<R> {
+    reader: BufReader<R>,
     eof: bool,
     pub line: String,
+    pub line_number: usize,
+    indent: usize,
 }
 
-impl Parser {
-    pub fn new(file: File) -> Self {
+impl<R: Read> Parser<R> {
+    pub fn new(reader: R) -> Self {
         Parser {
-            reader: BufReader::new(file),
+            reader: BufReader::new(reader),
             eof: false,
             line: String::new(),
+            line_number: 0,
+            indent: 0,
         }
     }
 
@@ -28,6 +35,8 @@ impl Parser {
 
         if let Ok(0) = self.reader.read_line(&mut line) {
             self.eof = true;
+        } else {
+            self.line_number += 1;
         }
 
         // Remove Comments
@@ -37,6 +46,7 @@ impl Parser {
         }
 
         // Remove Whitespace
+        self.indent = line.len() - line.trim_start().len();
         self.line = line.trim().to_string();
 
         // Skip over empty lines
@@ -45,62 +55,98 @@ impl Parser {
         }
     }
 
-    #[allow(non_snake_case)]
-    pub fn commandType(&self) -> CommandType {
-        if self.line.starts_with("@") {
-            return CommandType::A_COMMAND
-        }
+    /// The current command as a typed Instruction, located in the source.
+    pub fn instruction(&self) -> Result<Instruction, AssemblyError> {
+        self.line.parse().map_err(|mut e: AssemblyError| {
+            e.line = self.line_number;
+            e.column += self.indent;
+            e
+        })
+    }
+}
 
-        if self.line.starts_with("(") && self.line.ends_with(")") {
-            return CommandType::L_COMMAND
-        }
 
-        CommandType::C_COMMAND
-    }
+/// An instruction, with the line and 1-based column it starts at.
+#[derive(Clone)]
+pub struct Command {
+    pub instruction: Instruction,
+    pub line: usize,
+    pub column: usize,
+}
 
-    pub fn symbol(&self) -> String {
-        String::from(self.line[1..].replacen(")", "", 1))
-    }
+/// Parse every command of `source` in one read. Local labels are renamed
+/// to their unique scoped names.
+pub fn parse(source: &str) -> Result<Vec<Command>, AssemblyError> {
+    let mut parser = Parser::new(source.as_bytes());
+    let mut commands = Vec::new();
+    let mut global = String::new();
 
-    pub fn dest(&self) -> String {
-        let mut dest = self.line.clone();
+    parser.advance();
 
-        if let Some(i) = dest.find("=") {
-            dest = dest.split_at(i).0.to_string();
-        }
+    while parser.hasMoreCommands() {
+        let mut instruction = parser.instruction()?;
+        scope(&mut instruction, &mut global);
+
+        commands.push(Command {
+            instruction,
+            line: parser.line_number,
+            column: parser.indent + 1,
+        });
 
-        dest
+        parser.advance();
     }
 
-    pub fn comp(&self) -> String {
-        let mut comp = self.line.clone();
+    Ok(commands)
+}
 
-        if let Some(i) = comp.find("=") {
-            comp = comp.split_at(i + 1).1.to_string();
+// Qualify local labels by the nearest preceding global label
+fn scope(instruction: &mut Instruction, global: &mut String) {
+    let qualify = |symbol: &mut String, global: &str| {
+        if symboltable::is_local(symbol) {
+            *symbol = symboltable::scoped(global, symbol);
         }
+    };
+
+    match instruction {
+        Instruction::Label(label) if symboltable::is_local(label) => qualify(label, global),
+        Instruction::Label(label) => global.clone_from(label),
+        Instruction::A(Address::Symbol(symbol)) => qualify(symbol, global),
+        Instruction::A(Address::Expr(expr)) => expr.symbols_mut().for_each(|s| qualify(s, global)),
+        _ => (),
+    }
+}
 
-        if let Some(i) = comp.find(";") {
-            comp = comp.split_at(i).0.to_string();
-        }
 
-        comp
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn jump(&self) -> String {
-        let mut jump = self.line.clone();
+    #[test]
+    fn test_parse_locates_commands() {
+        let commands = parse("// comment\n\n  @i // i\n(LOOP)\n    0;JMP\n").unwrap();
+        let located: Vec<(String, usize, usize)> = commands.iter()
+            .map(|c| (c.instruction.to_string(), c.line, c.column))
+            .collect();
 
-        if let Some(i) = jump.find(";") {
-            jump = jump.split_at(i + 1).1.to_string();
-        }
+        assert_eq!(located, vec!(
+            (String::from("@i"), 3, 3),
+            (String::from("(LOOP)"), 4, 1),
+            (String::from("0;JMP"), 5, 5),
+        ));
+    }
+
+    #[test]
+    fn test_parse_scopes_local_labels() {
+        let commands = parse("(.start)\n(MAIN)\n(.loop)\n@.loop+1\n(NEXT)\n@.loop\n").unwrap();
+        let instructions: Vec<String> = commands.iter().map(|c| c.instruction.to_string()).collect();
 
-        jump
+        assert_eq!(instructions, vec!("(%.start)", "(MAIN)", "(MAIN%.loop)", "@MAIN%.loop+1", "(NEXT)", "@NEXT%.loop"));
     }
-}
 
+    #[test]
+    fn test_parse_error_is_located() {
+        let e = parse("@1\n  D=D+2\n").err().unwrap();
 
-#[allow(non_camel_case_types)]
-pub enum CommandType {
-    A_COMMAND,
-    C_COMMAND,
-    L_COMMAND,
+        assert_eq!((e.line, e.column, e.text.as_str()), (2, 5, "D+2"));
+    }
 }
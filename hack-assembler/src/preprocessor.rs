@@ -0,0 +1,414 @@
+//! Preprocessor: Expand macros and includes before parsing.
+//!
+//! ```text
+//! .include "runtime.asm"
+//!
+//! .macro PUSH_D
+//!     @SP
+//!     AM=M+1
+//!     A=A-1
+//!     M=D
+//! .endm
+//!
+//! .macro JUMP_IF_ZERO target
+//!     @\target
+//!     D;JEQ
+//! .endm
+//!
+//! .pin result 0x100
+//! ```
+//!
+//! A line whose first word names a macro is replaced by the macro body, with
+//! each `\param` substituted by the corresponding argument. Arguments are
+//! separated by whitespace or commas. Labels defined in a macro body are
+//! renamed per expansion, so a macro may be used more than once, e.g.
+//! `(LOOP)` in the first expansion of `WAIT` becomes `(.WAIT.1$LOOP)`. The
+//! new names are local labels, so they neither end the caller's scope nor
+//! are exported from an object. Directives may not be used in a macro body.
+//!
+//! `.pin` places a named variable at a fixed RAM address, rather than the
+//! next free one.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{AssemblyError, ErrorKind, Location, Warning};
+use crate::instruction::Address;
+use crate::symboltable;
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Expanded source, with the origin of every line.
+pub struct Preprocessed {
+    pub source: String,
+    /// Variables pinned to fixed RAM addresses.
+    pub pins: Vec<(String, u16)>,
+    origins: Vec<Origin>,
+}
+
+#[derive(Clone)]
+struct Origin {
+    location: Location,
+    expansions: Vec<(String, Location)>,
+}
+
+impl Preprocessed {
+    /// Relocate an error in the expanded source to the line it came from.
+    pub fn locate(&self, mut error: AssemblyError) -> AssemblyError {
+        if let Some(origin) = self.origin(error.line) {
+            error.line = origin.location.line;
+            error.file = origin.location.file.clone();
+            error.expansions = origin.expansions.clone();
+        }
+
+        error
+    }
+
+    /// Relocate a warning in the expanded source to the line it came from.
+    pub fn locate_warning(&self, mut warning: Warning) -> Warning {
+        if let Some(origin) = self.origin(warning.line) {
+            warning.line = origin.location.line;
+            warning.file = origin.location.file.clone();
+            warning.expansions = origin.expansions.clone();
+        }
+
+        warning
+    }
+
+    fn origin(&self, line: usize) -> Option<&Origin> {
+        line.checked_sub(1).and_then(|i| self.origins.get(i))
+    }
+
+    /// Expanded lines, each with the line it came from and the innermost
+    /// macro expansion it is in, if any.
+    pub fn lines(&self) -> impl Iterator<Item = (&Location, Option<&(String, Location)>, &str)> {
+        self.origins.iter().zip(self.source.lines()).map(|(o, text)| (&o.location, o.expansions.first(), text))
+    }
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    labels: Vec<String>,
+    body: Vec<(String, Location)>,
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    includes: Vec<PathBuf>,
+    expansions: usize,
+    output: Preprocessed,
+}
+
+/// Expand `source`, read from `path` if it is a file. Includes are resolved
+/// relative to the including file, or the working directory.
+pub fn preprocess(source: &str, path: Option<&Path>) -> Result<Preprocessed, AssemblyError> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        includes: path.and_then(|p| p.canonicalize().ok()).into_iter().collect(),
+        expansions: 0,
+        output: Preprocessed { source: String::new(), pins: Vec::new(), origins: Vec::new() },
+    };
+
+    let dir = path.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    preprocessor.file(source, None, dir)?;
+
+    Ok(preprocessor.output)
+}
+
+impl Preprocessor {
+    fn file(&mut self, source: &str, file: Option<String>, dir: &Path) -> Result<(), AssemblyError> {
+        let mut lines = source.lines().enumerate();
+
+        while let Some((i, text)) = lines.next() {
+            let location = Location { file: file.clone(), line: i + 1 };
+            let words = split_words(text);
+            let error = |kind, text: &str| located(kind, &location, text, &[]);
+
+            match words.first().map(String::as_str) {
+                Some(".macro") => {
+                    let name = words.get(1).ok_or_else(|| error(ErrorKind::InvalidSymbol, ".macro"))?;
+
+                    if !symboltable::is_valid(name) {
+                        return Err(error(ErrorKind::InvalidSymbol, name))
+                    }
+
+                    let mut body = Vec::new();
+
+                    loop {
+                        let (j, text) = lines.next().ok_or_else(|| error(ErrorKind::UnterminatedMacro, name))?;
+                        let location = Location { file: file.clone(), line: j + 1 };
+
+                        match split_words(text).first().map(String::as_str) {
+                            Some(".endm") => break,
+                            Some(".macro") => {
+                                return Err(located(ErrorKind::UnterminatedMacro, &location, name, &[]))
+                            },
+                            Some(word) if word.starts_with('.') => {
+                                return Err(located(ErrorKind::DirectiveInMacro, &location, word, &[]))
+                            },
+                            _ => body.push((text.to_string(), location)),
+                        }
+                    }
+
+                    let labels = body.iter().filter_map(|(text, _)| label(text)).collect();
+                    let params = words[2..].to_vec();
+                    self.macros.insert(name.clone(), Macro { params, labels, body });
+                },
+                Some(".endm") => return Err(error(ErrorKind::UnexpectedEndMacro, ".endm")),
+                Some(".pin") => {
+                    let (name, address) = match &words[1..] {
+                        [name, address] => (name, address),
+                        _ => return Err(error(ErrorKind::UnrecognisedDirective, text.trim())),
+                    };
+
+                    if !symboltable::is_valid(name) || symboltable::is_local(name) {
+                        return Err(error(ErrorKind::InvalidSymbol, name))
+                    }
+
+                    match address.parse::<Address>() {
+                        Ok(Address::Value(address)) => self.output.pins.push((name.clone(), address)),
+                        _ => return Err(error(ErrorKind::InvalidSymbol, address)),
+                    }
+                },
+                Some(".include") => {
+                    let included = text.split('"').nth(1)
+                        .ok_or_else(|| error(ErrorKind::UnrecognisedDirective, text.trim()))?;
+                    let path = dir.join(included);
+
+                    let source = fs::read_to_string(&path).map_err(|e| {
+                        let mut e = AssemblyError::io(e);
+                        e.file = location.file.clone();
+                        e.line = location.line;
+                        e.text = included.to_string();
+                        e
+                    })?;
+
+                    let canonical = path.canonicalize()?;
+                    if self.includes.contains(&canonical) {
+                        return Err(error(ErrorKind::IncludeCycle, included))
+                    }
+
+                    self.includes.push(canonical);
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    self.file(&source, Some(path.to_string_lossy().into_owned()), dir)?;
+                    self.includes.pop();
+                },
+                Some(word) if word.starts_with('.') => {
+                    return Err(error(ErrorKind::UnrecognisedDirective, word))
+                },
+                Some(word) if self.macros.contains_key(word) => {
+                    self.expand(word, &words[1..], &location, &[], 0)?;
+                },
+                _ => self.emit(text, location, Vec::new()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str, args: &[String], site: &Location,
+              outer: &[(String, Location)], depth: usize) -> Result<(), AssemblyError> {
+        let m = self.macros[name].clone();
+
+        if args.len() != m.params.len() {
+            return Err(located(ErrorKind::MacroArguments, site, name, outer))
+        }
+
+        if depth == MAX_EXPANSION_DEPTH {
+            return Err(located(ErrorKind::MacroRecursion, site, name, outer))
+        }
+
+        self.expansions += 1;
+        let expansion = self.expansions;
+
+        let mut expansions = vec!((name.to_string(), site.clone()));
+        expansions.extend_from_slice(outer);
+
+        // Longest first, so \ab is not substituted as \a followed by b
+        let mut params: Vec<(&String, &String)> = m.params.iter().zip(args).collect();
+        params.sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
+
+        for (text, location) in m.body.iter() {
+            let mut text = text.clone();
+
+            for (param, arg) in params.iter() {
+                text = text.replace(&format!("\\{}", param), arg);
+            }
+
+            // Renamed labels are local, so they don't end the caller's scope
+            for label in m.labels.iter() {
+                text = replace_symbol(&text, label, &format!(".{}.{}${}", name, expansion, label));
+            }
+
+            let words = split_words(&text);
+
+            match words.first() {
+                Some(word) if self.macros.contains_key(word) => {
+                    self.expand(word, &words[1..], location, &expansions, depth + 1)?;
+                },
+                _ => self.emit(&text, location.clone(), expansions.clone()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn emit(&mut self, text: &str, location: Location, expansions: Vec<(String, Location)>) {
+        self.output.source.push_str(text);
+        self.output.source.push('\n');
+        self.output.origins.push(Origin { location, expansions });
+    }
+}
+
+fn located(kind: ErrorKind, location: &Location, text: &str,
+           expansions: &[(String, Location)]) -> AssemblyError {
+    let mut error = AssemblyError::new(kind, location.line, 1, text);
+    error.file = location.file.clone();
+    error.expansions = expansions.to_vec();
+    error
+}
+
+// Whitespace or comma separated words, ignoring comments
+fn split_words(text: &str) -> Vec<String> {
+    text.split("//").next().unwrap()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|w| !w.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn label(text: &str) -> Option<String> {
+    let text = text.split("//").next().unwrap().trim();
+    text.strip_prefix('(')?.strip_suffix(')').map(String::from)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+// Replace whole occurrences of the symbol `from`
+fn replace_symbol(text: &str, from: &str, to: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(is_symbol_char) {
+        let end = rest[start..].find(|c| !is_symbol_char(c)).map_or(rest.len(), |e| start + e);
+        let symbol = &rest[start..end];
+
+        result.push_str(&rest[..start]);
+        result.push_str(if symbol == from { to } else { symbol });
+        rest = &rest[end..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str) -> String {
+        preprocess(source, None).unwrap().source
+    }
+
+    #[test]
+    fn test_source_without_directives_is_unchanged() {
+        assert_eq!(expand("@1\n(LOOP)\n  D=M // comment\n"), "@1\n(LOOP)\n  D=M // comment\n");
+    }
+
+    #[test]
+    fn test_expand_macro_with_arguments() {
+        let source = ".macro COPY from, to\n@\\from\nD=M\n@\\to\nM=D\n.endm\nCOPY R1, R2\n";
+
+        assert_eq!(expand(source), "@R1\nD=M\n@R2\nM=D\n");
+    }
+
+    #[test]
+    fn test_expand_macro_labels_are_unique() {
+        let source = ".macro WAIT\n(LOOP)\n@LOOP\n0;JMP\n.endm\nWAIT\nWAIT\n";
+
+        assert_eq!(expand(source), "\
+(.WAIT.1$LOOP)
+@.WAIT.1$LOOP
+0;JMP
+(.WAIT.2$LOOP)
+@.WAIT.2$LOOP
+0;JMP
+");
+    }
+
+    #[test]
+    fn test_expand_nested_macro_labels_are_unique() {
+        let source = ".macro SKIP\n@END\n0;JMP\n(END)\n.endm\n.macro TWICE\nSKIP\n(END)\n.endm\nTWICE\n";
+
+        assert_eq!(expand(source), "@.SKIP.2$END\n0;JMP\n(.SKIP.2$END)\n(.TWICE.1$END)\n");
+    }
+
+    #[test]
+    fn test_expand_macro_local_labels_stay_local() {
+        let source = ".macro WAIT\n(.loop)\n@.loop\n0;JMP\n.endm\nWAIT\n";
+
+        assert_eq!(expand(source), "(.WAIT.1$.loop)\n@.WAIT.1$.loop\n0;JMP\n");
+    }
+
+    #[test]
+    fn test_expand_macro_labels_keep_the_callers_scope() {
+        let source = ".macro WAIT\n(LOOP)\n@LOOP\n0;JMP\n.endm\n(MAIN)\n(.top)\nWAIT\n@.top\n";
+        let commands = crate::parser::parse(&expand(source)).unwrap();
+        let symbols: Vec<String> = commands.iter().map(|c| c.instruction.to_string()).collect();
+
+        assert_eq!(symbols, vec!("(MAIN)", "(MAIN%.top)", "(MAIN%.WAIT.1$LOOP)", "@MAIN%.WAIT.1$LOOP", "0;JMP", "@MAIN%.top"));
+    }
+
+    #[test]
+    fn test_expand_nested_macros() {
+        let source = ".macro INC\nM=M+1\n.endm\n.macro INC2 x\n@\\x\nINC\nINC\n.endm\nINC2 i\n";
+
+        assert_eq!(expand(source), "@i\nM=M+1\nM=M+1\n");
+    }
+
+    #[test]
+    fn test_replace_symbol_whole_words() {
+        assert_eq!(replace_symbol("@LOOP+1 // LOOPS", "LOOP", "X"), "@X+1 // LOOPS");
+    }
+
+    #[test]
+    fn test_locate_error_in_macro_body() {
+        let source = "@1\n.macro BAD\nD=D+2\n.endm\nBAD\n";
+        let preprocessed = preprocess(source, None).unwrap();
+        let error = AssemblyError::new(ErrorKind::UnrecognisedComp, 2, 3, "D+2");
+
+        let error = preprocessed.locate(error);
+
+        assert_eq!(error.line, 3);
+        assert_eq!(error.expansions, vec!((String::from("BAD"), Location { file: None, line: 5 })));
+    }
+
+    #[test]
+    fn test_pin_variables() {
+        let preprocessed = preprocess("@1\n.pin result 0x100\n.pin flag 7\n@result\n", None).unwrap();
+
+        assert_eq!(preprocessed.source, "@1\n@result\n");
+        assert_eq!(preprocessed.pins, vec!((String::from("result"), 0x100), (String::from("flag"), 7)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |source| preprocess(source, None).err().map(|e| e.kind);
+
+        assert!(matches!(kind(".macro M\n@1\n"), Some(ErrorKind::UnterminatedMacro)));
+        assert!(matches!(kind(".endm\n"), Some(ErrorKind::UnexpectedEndMacro)));
+        assert!(matches!(kind(".bogus\n"), Some(ErrorKind::UnrecognisedDirective)));
+        assert!(matches!(kind(".macro M x\n.endm\nM\n"), Some(ErrorKind::MacroArguments)));
+        assert!(matches!(kind(".macro M\nM\n.endm\nM\n"), Some(ErrorKind::MacroRecursion)));
+        assert!(matches!(kind(".macro M\n.pin x 16\n.endm\n"), Some(ErrorKind::DirectiveInMacro)));
+        assert!(matches!(kind(".macro M\n.include \"x.asm\"\n.endm\n"), Some(ErrorKind::DirectiveInMacro)));
+        assert!(matches!(kind(".pin x\n"), Some(ErrorKind::UnrecognisedDirective)));
+        assert!(matches!(kind(".pin 1x 16\n"), Some(ErrorKind::InvalidSymbol)));
+        assert!(matches!(kind(".pin x y\n"), Some(ErrorKind::InvalidSymbol)));
+    }
+}
@@ -0,0 +1,141 @@
+//! SymbolMap: Export the resolved SymbolTable for debuggers and profilers,
+//! and read symbol maps back.
+
+use std::io::{prelude::*, BufWriter};
+
+use crate::error::{AssemblyError, ErrorKind};
+use crate::symboltable::{self, Kind, SymbolTable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolFormat {
+    /// `NAME ADDRESS KIND` lines, readable by `parse_labels`.
+    Text,
+    /// An array of `{"name", "address", "kind"}` objects.
+    Json,
+}
+
+pub fn write(symbol_table: &SymbolTable, format: SymbolFormat, dst: impl Write) -> Result<(), AssemblyError> {
+    let mut writer = BufWriter::new(dst);
+    let entries: Vec<_> = symbol_table.entries().into_iter()
+        .map(|(name, address, kind)| (symboltable::readable(name), address, kind))
+        .collect();
+
+    match format {
+        SymbolFormat::Text => {
+            for (name, address, kind) in entries {
+                writeln!(&mut writer, "{} {} {}", name, address, kind)?;
+            }
+        },
+        SymbolFormat::Json => {
+            writeln!(&mut writer, "[")?;
+
+            for (i, (name, address, kind)) in entries.iter().enumerate() {
+                let comma = if i + 1 < entries.len() { "," } else { "" };
+                writeln!(&mut writer, "  {{\"name\": \"{}\", \"address\": {}, \"kind\": \"{}\"}}{}",
+                         name, address, kind, comma)?;
+            }
+
+            writeln!(&mut writer, "]")?;
+        },
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Parse a symbol map of `NAME ADDRESS [KIND]` lines, e.g. to pin
+/// variables to fixed RAM addresses. Entries with a kind other than
+/// `variable` are skipped. Blank lines and `//` comments are ignored.
+pub fn parse_variables(map: &str) -> Result<Vec<(String, u16)>, AssemblyError> {
+    parse(map, Kind::Variable)
+}
+
+// Entries of `kind`, or with no kind
+pub(crate) fn parse(sym: &str, kind: Kind) -> Result<Vec<(String, u16)>, AssemblyError> {
+    let mut symbols = Vec::new();
+    let kind = kind.to_string();
+
+    for (i, line) in sym.lines().enumerate() {
+        let line = line.split("//").next().unwrap();
+        let mut fields = line.split_whitespace();
+
+        let (name, address) = match (fields.next(), fields.next()) {
+            (None, _) => continue,
+            (Some(name), Some(address)) => (name, address),
+            (Some(name), None) => {
+                return Err(AssemblyError::new(ErrorKind::InvalidSymbol, i + 1, 1, name))
+            },
+        };
+
+        if fields.next().is_some_and(|k| k != kind) {
+            continue
+        }
+
+        if !symboltable::is_valid(name) {
+            return Err(AssemblyError::new(ErrorKind::InvalidSymbol, i + 1, 1, name))
+        }
+
+        match address.parse::<u16>() {
+            Ok(address) => symbols.push((name.to_string(), address)),
+            Err(_) => {
+                let column = line.find(address).unwrap() + 1;
+                return Err(AssemblyError::new(ErrorKind::InvalidSymbol, i + 1, column, address))
+            },
+        }
+    }
+
+    Ok(symbols)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symboltable::Kind;
+
+    fn symbol_table() -> SymbolTable {
+        let mut s = SymbolTable::new();
+        s.addEntry("LOOP", 4, Kind::Label);
+        s.addEntry("i", 16, Kind::Variable);
+        s
+    }
+
+    fn write_str(format: SymbolFormat) -> String {
+        let mut sym = Vec::new();
+        write(&symbol_table(), format, &mut sym).unwrap();
+        String::from_utf8(sym).unwrap()
+    }
+
+    #[test]
+    fn test_write_text() {
+        let sym = write_str(SymbolFormat::Text);
+        let lines: Vec<&str> = sym.lines().collect();
+
+        assert_eq!(lines[..3], ["LOOP 4 label", "i 16 variable", "R0 0 predefined"]);
+        assert_eq!(lines.last(), Some(&"KBD 24576 predefined"));
+    }
+
+    #[test]
+    fn test_parse_variables_skips_other_kinds() {
+        let variables = parse_variables("LOOP 4 label\ni 16 variable\nj 100\nR0 0 predefined\n").unwrap();
+
+        assert_eq!(variables, vec!((String::from("i"), 16), (String::from("j"), 100)));
+    }
+
+    #[test]
+    fn test_write_json() {
+        let sym = write_str(SymbolFormat::Json);
+        let lines: Vec<&str> = sym.lines().collect();
+
+        assert_eq!(lines[..3], [
+            "[",
+            "  {\"name\": \"LOOP\", \"address\": 4, \"kind\": \"label\"},",
+            "  {\"name\": \"i\", \"address\": 16, \"kind\": \"variable\"},",
+        ]);
+        assert_eq!(lines[lines.len() - 2..], [
+            "  {\"name\": \"KBD\", \"address\": 24576, \"kind\": \"predefined\"}",
+            "]",
+        ]);
+    }
+}
@@ -1,8 +1,9 @@
-/// SymbolTable: Keep a correspondence between symbolic labels and numeric addresses.
+//! SymbolTable: Keep a correspondence between symbolic labels and numeric addresses.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-static PREDEFINED_SYMBOLS: &'static [(&'static str, u16)] = &[
+static PREDEFINED_SYMBOLS: &[(&str, u16)] = &[
     ("SP",   0x0000),
     ("LCL",  0x0001),
     ("ARG",  0x0002),
@@ -28,26 +29,48 @@ static PREDEFINED_SYMBOLS: &'static [(&'static str, u16)] = &[
     ("KBD", 0x6000),
 ];
 
+type Entry = (u16, Kind);
+
 pub struct SymbolTable {
-    pub hash: HashMap<String, u16>,
+    pub hash: HashMap<String, Entry>,
+    pinned: HashSet<u16>,
 }
 
 impl SymbolTable {
     pub const NEXT_AVAILABLE_RAM_ADDRESS: u16 = 16;
 
     pub fn new() -> Self {
-        let mut symboltable = SymbolTable { hash: HashMap::new() };
+        let mut symboltable = SymbolTable { hash: HashMap::new(), pinned: HashSet::new() };
 
         for (k, v) in PREDEFINED_SYMBOLS.iter() {
-            symboltable.addEntry(k, *v);
+            symboltable.addEntry(k, *v, Kind::Predefined);
         }
 
         symboltable
     }
 
     #[allow(non_snake_case)]
-    pub fn addEntry(&mut self, symbol: &str, address: u16) {
-        self.hash.insert(symbol.to_string(), address);
+    pub fn addEntry(&mut self, symbol: &str, address: u16, kind: Kind) {
+        self.hash.insert(symbol.to_string(), (address, kind));
+    }
+
+    /// Add a variable at a fixed address, which `allocate` will skip.
+    pub fn pin(&mut self, symbol: &str, address: u16) {
+        self.addEntry(symbol, address, Kind::Variable);
+        self.pinned.insert(address);
+    }
+
+    /// Add a variable at the first address from `next` that isn't pinned,
+    /// and advance `next` past it.
+    pub fn allocate(&mut self, symbol: &str, next: &mut u16) -> u16 {
+        while self.pinned.contains(next) {
+            *next += 1;
+        }
+
+        let address = *next;
+        self.addEntry(symbol, address, Kind::Variable);
+        *next += 1;
+        address
     }
 
     pub fn contains(&self, symbol: &str) -> bool {
@@ -55,9 +78,84 @@ impl SymbolTable {
     }
 
     #[allow(non_snake_case)]
-    pub fn GetAddress(&self, symbol: &str) -> u16 {
-        *self.hash.get(symbol)
-            .expect(&format!("KeyError: {}", symbol))
+    pub fn GetAddress(&self, symbol: &str) -> Option<u16> {
+        self.hash.get(symbol).map(|e| e.0)
+    }
+
+    /// Symbols ordered by kind, then address, then name.
+    pub fn entries(&self) -> Vec<(&str, u16, Kind)> {
+        let mut entries: Vec<_> = self.hash.iter()
+            .map(|(k, v)| (k.as_str(), v.0, v.1))
+            .collect();
+
+        entries.sort_by_key(|&(name, address, kind)| (kind, address, name));
+        entries
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
     }
 }
 
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Kind {
+    Label,
+    Variable,
+    Defined,
+    Predefined,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Kind::Label      => "label",
+            Kind::Variable   => "variable",
+            Kind::Defined    => "defined",
+            Kind::Predefined => "predefined",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+
+/// A symbol is a sequence of letters, digits, '_', '.', '$' and ':' not
+/// beginning with a digit.
+pub fn is_valid(symbol: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+
+    match symbol.chars().next() {
+        Some(c) if c.is_ascii_digit() => false,
+        Some(_) => symbol.chars().all(valid),
+        None => false,
+    }
+}
+
+// Joins a global label and a local label into a name no symbol can have
+const SCOPE: char = '%';
+
+/// Local labels begin with '.', e.g. `(.loop)`, and are scoped to the
+/// nearest preceding global label.
+pub fn is_local(symbol: &str) -> bool {
+    symbol.starts_with('.')
+}
+
+/// Unique name of the local label `local` in the scope of `global`.
+pub fn scoped(global: &str, local: &str) -> String {
+    format!("{}{}{}", global, SCOPE, local)
+}
+
+pub fn is_scoped(symbol: &str) -> bool {
+    symbol.contains(SCOPE)
+}
+
+/// Readable name of a symbol, e.g. `MAIN.loop` for `.loop` after `(MAIN)`.
+pub fn readable(symbol: &str) -> String {
+    symbol.replacen(SCOPE, "", 1)
+}
+
+/// The symbol as written in the source, without its scope.
+pub fn unscoped(symbol: &str) -> &str {
+    symbol.rsplit(SCOPE).next().unwrap()
+}
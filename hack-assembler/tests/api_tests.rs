@@ -0,0 +1,244 @@
+//! Tests!
+
+use assembler::{assemble_io, assemble_str};
+
+
+const ADD: &str = "\
+// Computes R0 = 2 + 3
+@2
+D=A
+@3
+D=D+A
+@0
+M=D
+";
+
+
+#[test]
+fn test_assemble_str_add() {
+    let words = assemble_str(ADD).unwrap();
+
+    assert_eq!(words, vec!(0x0002, 0xec10, 0x0003, 0xe090, 0x0000, 0xe308));
+}
+
+#[test]
+fn test_assemble_str_resolves_labels_and_variables() {
+    let words = assemble_str("@i\nM=1\n(LOOP)\n@LOOP\n0;JMP\n@j\n").unwrap();
+
+    assert_eq!(words, vec!(16, 0xefc8, 2, 0xea87, 17));
+}
+
+#[test]
+fn test_assemble_str_constant_expressions() {
+    let words = assemble_str("@SCREEN+32\n@0x4000\n@'A'\n(END)\n@END-1\n").unwrap();
+
+    assert_eq!(words, vec!(0x4020, 0x4000, 65, 2));
+}
+
+#[test]
+fn test_assemble_str_local_labels() {
+    let words = assemble_str("(A)\n(.loop)\n@.loop\n0;JMP\n(B)\n@.loop\n(.loop)\n0;JMP\n").unwrap();
+
+    assert_eq!(words, vec!(0, 0xea87, 3, 0xea87));
+}
+
+#[test]
+fn test_assemble_str_no_trailing_newline() {
+    assert_eq!(assemble_str("@5").unwrap(), vec!(5));
+}
+
+#[test]
+fn test_assemble_str_empty() {
+    assert_eq!(assemble_str("").unwrap(), vec!());
+}
+
+#[test]
+fn test_assemble_io_writes_hack_lines() {
+    let mut hack = Vec::new();
+
+    assemble_io(ADD.as_bytes(), &mut hack).unwrap();
+
+    assert_eq!(std::str::from_utf8(&hack).unwrap(), "\
+0000000000000010
+1110110000010000
+0000000000000011
+1110000010010000
+0000000000000000
+1110001100001000
+");
+}
+
+#[test]
+fn test_assemble_with_listing() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Listing.asm");
+    let hack = dir.join("Listing.hack");
+    std::fs::write(&asm, ADD).unwrap();
+
+    let options = assembler::Options { listing: true, ..Default::default() };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let lst = std::fs::read_to_string(dir.join("Listing.lst")).unwrap();
+    let lines: Vec<&str> = lst.lines().collect();
+
+    assert_eq!(lines.len(), 1 + ADD.lines().count());
+    assert_eq!(lines[4], "0002  0000000000000011  0003      4  @3");
+}
+
+#[test]
+fn test_assemble_with_listing_locates_included_lines() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("ListingMain.asm");
+    let hack = dir.join("ListingMain.hack");
+    std::fs::write(&asm, "@1\n.include \"ListingLib.asm\"\nD=A\n").unwrap();
+    std::fs::write(dir.join("ListingLib.asm"), "// lib\n@2\n").unwrap();
+
+    let options = assembler::Options { listing: true, ..Default::default() };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let lst = std::fs::read_to_string(dir.join("ListingMain.lst")).unwrap();
+    let lines: Vec<&str> = lst.lines().collect();
+    let lib = dir.join("ListingLib.asm").to_string_lossy().into_owned();
+
+    assert_eq!(lines[1], "0000  0000000000000001  0001      1  @1");
+    assert_eq!(lines[3], format!("0001  0000000000000010  0002  {}:2  @2", lib));
+    assert_eq!(lines[4], "0002  1110110000010000  EC10      3  D=A");
+}
+
+#[test]
+fn test_assemble_with_symbols_round_trips_through_disassembler() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Symbols.asm");
+    let hack = dir.join("Symbols.hack");
+    let source = "@i\nM=0\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n";
+    std::fs::write(&asm, source).unwrap();
+
+    let options = assembler::Options { symbols: Some(assembler::SymbolFormat::Text), ..Default::default() };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let sym = std::fs::read_to_string(dir.join("Symbols.sym")).unwrap();
+    assert!(sym.starts_with("LOOP 2 label\ni 16 variable\n"));
+
+    let disassembled = assembler::disassemble(&hack, Some(&dir.join("Symbols.sym"))).unwrap();
+    assert_eq!(disassembled, source.replace("@i", "@16"));
+}
+
+#[test]
+fn test_lint_str_warns_about_typos() {
+    let warnings = assembler::lint_str("@count\nM=0\n@cuont\nM=M+1\n@count\n").unwrap();
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, assembler::WarningKind::SingleUseVariable);
+    assert_eq!(warnings[0].to_string(), "3:2: warning: Variable used only once, is it a typo? 'cuont'");
+}
+
+#[test]
+fn test_assemble_with_warns_about_variables_in_screen() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Variables.asm");
+    let hack = dir.join("Variables.hack");
+    let source: String = (0..16384 - 16 + 2).map(|i| format!("@v{}\n", i)).collect();
+    std::fs::write(&asm, source).unwrap();
+
+    let warnings = assembler::assemble_with(&asm, &hack, &Default::default()).unwrap();
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, assembler::WarningKind::VariableInMemoryMap);
+    assert_eq!((warnings[0].line, warnings[0].text.as_str()), (16384 - 16 + 1, "v16368"));
+}
+
+#[test]
+fn test_assemble_with_warns_about_variables_past_a_pinned_screen() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // With 16384 pinned, the first variable past 16383 is at 16385
+    let asm = dir.join("PinnedScreen.asm");
+    let hack = dir.join("PinnedScreen.hack");
+    let variables: String = (0..16384 - 16 + 2).map(|i| format!("@v{}\n", i)).collect();
+    std::fs::write(&asm, format!(".pin P 16384\n@P\n{}", variables)).unwrap();
+
+    let warnings = assembler::assemble_with(&asm, &hack, &Default::default()).unwrap();
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, assembler::WarningKind::VariableInMemoryMap);
+    assert_eq!((warnings[0].line, warnings[0].text.as_str()), (16384 - 16 + 3, "v16368"));
+}
+
+#[test]
+fn test_assemble_with_shows_scoped_names_of_local_labels() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Locals.asm");
+    let hack = dir.join("Locals.hack");
+    std::fs::write(&asm, "(MAIN)\n(.loop)\n@.loop\n0;JMP\n").unwrap();
+
+    let options = assembler::Options {
+        listing: true,
+        symbols: Some(assembler::SymbolFormat::Text),
+        ..Default::default()
+    };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let sym = std::fs::read_to_string(dir.join("Locals.sym")).unwrap();
+    assert!(sym.starts_with("MAIN 0 label\nMAIN.loop 0 label\n"));
+
+    let lst = std::fs::read_to_string(dir.join("Locals.lst")).unwrap();
+    assert_eq!(lst.lines().nth(2), Some("0000                              2  (.loop)  = MAIN.loop"));
+}
+
+#[test]
+fn test_assemble_with_defines_and_pinned_variables() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Pinned.asm");
+    let hack = dir.join("Pinned.hack");
+    std::fs::write(&asm, ".pin result 0x100\n@DEBUG\n@result\n@x\n@y\n").unwrap();
+
+    let options = assembler::Options {
+        defines: vec!((String::from("DEBUG"), 5)),
+        variables: assembler::parse_variables("x 16\n").unwrap(),
+        ..Default::default()
+    };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let hack = std::fs::read_to_string(&hack).unwrap();
+    let words: Vec<u16> = hack.lines().map(|line| u16::from_str_radix(line, 2).unwrap()).collect();
+
+    assert_eq!(words, vec!(5, 0x100, 16, 17));
+}
+
+#[test]
+fn test_assemble_with_optimize_lists_folded_words() {
+    let dir = std::env::temp_dir().join("hack-assembler-api-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let asm = dir.join("Optimized.asm");
+    let hack = dir.join("Optimized.hack");
+    std::fs::write(&asm, "@7\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n@END\n0;JMP\nD=M\n(END)\n@END\n0;JMP\n").unwrap();
+
+    let options = assembler::Options { optimize: true, listing: true, ..Default::default() };
+    assembler::assemble_with(&asm, &hack, &options).unwrap();
+
+    let lst = std::fs::read_to_string(dir.join("Optimized.lst")).unwrap();
+    let lines: Vec<&str> = lst.lines().collect();
+
+    assert_eq!(lines[3..8], [
+        "0002  0000000000000000  0000      3  @SP",
+        "0003  1111110111101000  FDE8      3  ",
+        "0004  1110110010100000  ECA0      3  ",
+        "0005  1110001100001000  E308      3  ",
+        "                                  4  A=M",
+    ]);
+    assert_eq!(std::fs::read_to_string(&hack).unwrap().lines().count(), 10);
+}
@@ -0,0 +1,81 @@
+//! Tests!
+
+use assembler::{assemble_str, disassemble_str, disassemble_words, parse_labels, Labels};
+
+
+const MAX: &str = "\
+@R0
+D=M
+@R1
+D=D-M
+@OUTPUT_FIRST
+D;JGT
+@R1
+D=M
+@OUTPUT_D
+0;JMP
+(OUTPUT_FIRST)
+@R0
+D=M
+(OUTPUT_D)
+@R2
+M=D
+(END)
+@END
+0;JMP
+";
+
+
+fn hack(words: &[u16]) -> String {
+    words.iter().map(|w| format!("{:0>16b}\n", w)).collect()
+}
+
+#[test]
+fn test_round_trip_without_labels() {
+    let words = assemble_str(MAX).unwrap();
+    let asm = disassemble_str(&hack(&words), &Labels::new()).unwrap();
+
+    assert!(!asm.contains('('));
+    assert_eq!(assemble_str(&asm).unwrap(), words);
+}
+
+#[test]
+fn test_round_trip_with_labels() {
+    let words = assemble_str(MAX).unwrap();
+    let labels = parse_labels("OUTPUT_FIRST 10\nOUTPUT_D 12\nEND 14\n").unwrap();
+    let asm = disassemble_str(&hack(&words), &labels).unwrap();
+
+    assert_eq!(asm, MAX.replace("@R0", "@0").replace("@R1", "@1").replace("@R2", "@2"));
+    assert_eq!(assemble_str(&asm).unwrap(), words);
+}
+
+#[test]
+fn test_label_not_substituted_for_memory_access() {
+    let labels = parse_labels("LOOP 0\n").unwrap();
+    let asm = disassemble_words(&[0, 0xfc10, 0, 0xea87], &labels).unwrap();
+    let asm: Vec<String> = asm.iter().map(ToString::to_string).collect();
+
+    assert_eq!(asm, vec!("(LOOP)", "@0", "D=M", "@LOOP", "0;JMP"));
+}
+
+#[test]
+fn test_label_at_end_of_program() {
+    let labels = parse_labels("// comment\n\nEND 1 label\n").unwrap();
+    let asm = disassemble_words(&[0xea87], &labels).unwrap();
+
+    assert_eq!(asm.last().unwrap().to_string(), "(END)");
+}
+
+#[test]
+fn test_unrecognised_word() {
+    let e = disassemble_str("0000000000000001\n1010101010101010\n", &Labels::new()).unwrap_err();
+
+    assert_eq!(e.to_string(), "2:1: error: Unrecognised instruction word '1010101010101010'");
+}
+
+#[test]
+fn test_malformed_line() {
+    let e = disassemble_str("0101\n", &Labels::new()).unwrap_err();
+
+    assert_eq!((e.line, e.text.as_str()), (1, "0101"));
+}
@@ -0,0 +1,156 @@
+//! Tests!
+
+use std::fs;
+use std::path::PathBuf;
+
+use assembler::{AssemblyError, ErrorKind};
+
+
+fn assemble(name: &str, asm: &str) -> Result<String, AssemblyError> {
+    let dir = std::env::temp_dir().join("hack-assembler-error-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let src: PathBuf = dir.join(name).with_extension("asm");
+    let dst: PathBuf = src.with_extension("hack");
+    fs::write(&src, asm).unwrap();
+
+    assembler::assemble(&src, &dst).map(|_| fs::read_to_string(&dst).unwrap())
+}
+
+
+macro_rules! assembly_error_test {
+    ($name:ident $asm:tt -> $kind:pat, $line:tt:$column:tt $text:tt) => {
+        #[test]
+        fn $name() {
+            let e = assemble(stringify!($name), $asm).unwrap_err();
+
+            assert!(matches!(e.kind, $kind), "{:?}", e.kind);
+            assert_eq!((e.line, e.column), ($line, $column));
+            assert_eq!(e.text, $text);
+            assert!(e.file.unwrap().ends_with(concat!(stringify!($name), ".asm")));
+        }
+    }
+}
+
+
+assembly_error_test!(
+test_unrecognised_comp
+"@2\nD=A\n  D=D+2\n"
+-> ErrorKind::UnrecognisedComp, 3:5 "D+2"
+);
+
+assembly_error_test!(
+test_unrecognised_dest
+"// comment\n\nX=D\n"
+-> ErrorKind::UnrecognisedDest, 3:1 "X"
+);
+
+assembly_error_test!(
+test_unrecognised_jump
+"0;JMPP\n"
+-> ErrorKind::UnrecognisedJump, 1:3 "JMPP"
+);
+
+assembly_error_test!(
+test_invalid_symbol
+"@1abc\n"
+-> ErrorKind::InvalidSymbol, 1:2 "1abc"
+);
+
+assembly_error_test!(
+test_invalid_label
+"(LOOP)\n(2LOOP)\n"
+-> ErrorKind::InvalidSymbol, 2:2 "2LOOP"
+);
+
+assembly_error_test!(
+test_expression_undefined_symbol
+"@SCREEN+ARRAY\n"
+-> ErrorKind::UndefinedSymbol, 1:9 "ARRAY"
+);
+
+assembly_error_test!(
+test_expression_overflow
+"(START)\n@START-1\n"
+-> ErrorKind::Overflow, 2:2 "START-1"
+);
+
+assembly_error_test!(
+test_literal_overflow
+"  @0x10000\n"
+-> ErrorKind::Overflow, 1:4 "0x10000"
+);
+
+assembly_error_test!(
+test_undefined_local_label
+"(MAIN)\n@.loop\n(NEXT)\n(.loop)\n"
+-> ErrorKind::UndefinedSymbol, 2:2 ".loop"
+);
+
+assembly_error_test!(
+test_undefined_local_label_in_expression
+"(MAIN)\n@SCREEN+.row\n"
+-> ErrorKind::UndefinedSymbol, 2:9 ".row"
+);
+
+assembly_error_test!(
+test_constant_too_large
+"@32767\n@32768\n"
+-> ErrorKind::ConstantTooLarge, 2:2 "32768"
+);
+
+assembly_error_test!(
+test_expression_too_large
+"@SCREEN+0x4000\n"
+-> ErrorKind::ConstantTooLarge, 1:2 "SCREEN+0x4000"
+);
+
+assembly_error_test!(
+test_label_named_as_pinned_variable
+".pin x 100\n@x\nM=1\n(x)\n"
+-> ErrorKind::LabelConflict, 4:2 "x"
+);
+
+#[test]
+fn test_program_too_large() {
+    let e = assemble("test_program_too_large", &"D=0\n".repeat(32 * 1024 + 1)).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::ProgramTooLarge));
+    assert_eq!(e.line, 32 * 1024 + 1);
+}
+
+#[test]
+fn test_missing_file_is_io_error() {
+    let e = assembler::assemble("missing.asm".as_ref(), "missing.hack".as_ref())
+        .unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::Io(_)));
+    assert!(e.to_string().starts_with("missing.asm: error: "));
+}
+
+#[test]
+fn test_error_display() {
+    let e = assemble("test_error_display", "D=D+2\n").unwrap_err();
+
+    assert!(e.to_string().ends_with("test_error_display.asm:1:3: error: Unrecognised comp 'D+2'"));
+}
+
+#[test]
+fn test_valid_program() {
+    let hack = assemble("test_valid_program", "(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n").unwrap();
+
+    assert_eq!(hack, "\
+0000000000010000
+1111110111001000
+0000000000000000
+1110101010000111
+");
+}
+
+#[test]
+fn test_assemble_str_error_has_no_file() {
+    let e = assembler::assemble_str("@1\nD=D+2\n").unwrap_err();
+
+    assert!(e.file.is_none());
+    assert_eq!(e.to_string(), "2:3: error: Unrecognised comp 'D+2'");
+}
@@ -0,0 +1,88 @@
+//! Tests!
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use assembler::{assemble_str, ErrorKind, Format, Options};
+
+
+const MAIN: &str = "\
+// Sets R0 = 2 * x
+@x
+M=1
+@DOUBLE
+0;JMP
+(RETURN)
+@RETURN
+0;JMP
+";
+
+const DOUBLE: &str = "\
+(DOUBLE)
+@x
+D=M
+D=D+M
+@R0
+M=D
+@RETURN
+0;JMP
+";
+
+
+fn compile(dir: &Path, name: &str, asm: &str) -> PathBuf {
+    let src = dir.join(name).with_extension("asm");
+    let dst = src.with_extension("o");
+    fs::write(&src, asm).unwrap();
+
+    let options = Options { object: true, ..Default::default() };
+    assembler::assemble_with(&src, &dst, &options).unwrap();
+
+    dst
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join("hack-assembler-link-tests");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+
+#[test]
+fn test_link_matches_assembling_concatenated_source() {
+    let dir = temp_dir();
+    let objects = vec!(compile(&dir, "Main", MAIN), compile(&dir, "Double", DOUBLE));
+    let hack = dir.join("Linked.hack");
+
+    assembler::link(&objects, &hack, Format::Hack).unwrap();
+
+    let words = assemble_str(&format!("{}{}", MAIN, DOUBLE)).unwrap();
+    let linked: Vec<u16> = fs::read_to_string(&hack).unwrap()
+        .lines()
+        .map(|line| u16::from_str_radix(line, 2).unwrap())
+        .collect();
+
+    assert_eq!(linked, words);
+}
+
+#[test]
+fn test_link_duplicate_export_names_file() {
+    let dir = temp_dir();
+    let objects = vec!(compile(&dir, "First", "(START)\n"), compile(&dir, "Second", "(START)\n"));
+
+    let e = assembler::link(&objects, &dir.join("Duplicate.hack"), Format::Hack).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::DuplicateSymbol));
+    assert!(e.file.unwrap().ends_with("Second.o"));
+}
+
+#[test]
+fn test_link_invalid_object() {
+    let dir = temp_dir();
+    let object = dir.join("Invalid.o");
+    fs::write(&object, "HACK OBJECT\ncode 1\n").unwrap();
+
+    let e = assembler::link(&[&object], &dir.join("Invalid.hack"), Format::Hack).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::InvalidObject));
+    assert_eq!(e.line, 2);
+}
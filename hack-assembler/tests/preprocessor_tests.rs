@@ -0,0 +1,74 @@
+//! Tests!
+
+use std::fs;
+use std::path::PathBuf;
+
+use assembler::{AssemblyError, ErrorKind};
+
+
+fn assemble(dir: &str, files: &[(&str, &str)]) -> Result<Vec<u16>, AssemblyError> {
+    let dir: PathBuf = std::env::temp_dir().join("hack-assembler-preprocessor-tests").join(dir);
+    fs::create_dir_all(dir.join("lib")).unwrap();
+
+    for (name, asm) in files {
+        fs::write(dir.join(name), asm).unwrap();
+    }
+
+    let src = dir.join(files[0].0);
+    assembler::assemble(&src, &src.with_extension("hack"))?;
+
+    let hack = fs::read_to_string(src.with_extension("hack")).unwrap();
+    Ok(hack.lines().map(|l| u16::from_str_radix(l, 2).unwrap()).collect())
+}
+
+
+#[test]
+fn test_include_relative_to_including_file() {
+    let words = assemble("include", &[
+        ("Main.asm", ".include \"lib/push.asm\"\n@7\nD=A\nPUSH_D\n"),
+        ("lib/push.asm", ".include \"regs.asm\"\n.macro PUSH_D\n@SP\nAM=M+1\nA=A-1\nM=D\n.endm\n"),
+        ("lib/regs.asm", "@R0\n"),
+    ]).unwrap();
+
+    assert_eq!(words, vec!(0, 7, 0xec10, 0, 0xfde8, 0xeca0, 0xe308));
+}
+
+#[test]
+fn test_include_cycle() {
+    let e = assemble("cycle", &[
+        ("Main.asm", "@1\n.include \"A.asm\"\n"),
+        ("A.asm", "\n.include \"Main.asm\"\n"),
+    ]).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::IncludeCycle));
+    assert!(e.file.unwrap().ends_with("A.asm"));
+    assert_eq!(e.line, 2);
+}
+
+#[test]
+fn test_include_missing_file() {
+    let e = assemble("missing", &[("Main.asm", "\n.include \"nope.asm\"\n")]).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::Io(_)));
+    assert!(e.file.unwrap().ends_with("Main.asm"));
+    assert_eq!((e.line, e.text.as_str()), (2, "nope.asm"));
+}
+
+#[test]
+fn test_error_in_macro_points_to_body_and_expansion_site() {
+    let e = assemble("expansion", &[
+        ("Main.asm", ".include \"lib/bad.asm\"\n@1\nBAD\n"),
+        ("lib/bad.asm", ".macro BAD\n@1\nD=D+2\n.endm\n"),
+    ]).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::UnrecognisedComp));
+    assert!(e.file.as_ref().unwrap().ends_with("lib/bad.asm"));
+    assert_eq!(e.line, 3);
+
+    let (name, site) = &e.expansions[0];
+    assert_eq!(name, "BAD");
+    assert!(site.file.as_ref().unwrap().ends_with("Main.asm"));
+    assert_eq!(site.line, 3);
+
+    assert!(e.to_string().contains("\n    in expansion of macro 'BAD' at "));
+}
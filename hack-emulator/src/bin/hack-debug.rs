@@ -0,0 +1,123 @@
+//! Executable for debugging Hack programs interactively.
+//!
+//! Usage: hack-debug [--gdb PORT|-] <file.asm|file.hack>
+
+use std::env;
+use std::io::{self, prelude::*};
+use std::net::TcpListener;
+use std::path::Path;
+
+use emulator::{gdb, Debugger, Machine};
+
+const USAGE: &str = "\
+Usage: hack-debug [--gdb PORT|-] <file.asm|file.hack>
+
+Labels come from assembling <file.asm>, or from <file.sym> beside <file.hack>.
+
+Options:
+    --gdb PORT           Serve the GDB remote protocol on 127.0.0.1:PORT
+    --gdb -              Serve the GDB remote protocol on stdin and stdout";
+
+const HELP: &str = "\
+Commands:
+    break LABEL|ADDR     Stop before the instruction at a label or ROM address
+    delete [LABEL|ADDR]  Delete a breakpoint, or all breakpoints
+    watch RAM[ADDR]      Stop when RAM[ADDR] changes, where ADDR may be a variable
+    step [N]             Execute N instructions (default 1)
+    next                 Step, or over a VM call until it returns
+    continue             Run until a breakpoint, a watched change or the program halts
+    reverse-step [N]     Go back N instructions (default 1)
+    reverse-continue     Go back to the last breakpoint or watched change
+    last-write RAM[ADDR] Show the instruction that last wrote RAM[ADDR]
+    print EXPR           Print D, A, M, PC, a variable, RAM[X] or RAM[X..Y] inclusive
+    backtrace            Show the VM call frames
+    quit
+
+An empty line repeats the last command.";
+
+fn main() {
+    let mut gdb = None;
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gdb" => gdb = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--gdb=") => gdb = Some(arg["--gdb=".len()..].to_string()),
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| usage());
+
+    let mut machine = Machine::new();
+    let symbols = emulator::load(&mut machine, Path::new(&path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut debugger = Debugger::new(machine, symbols);
+
+    if let Some(gdb) = gdb {
+        if let Err(e) = serve_gdb(&mut debugger, &gdb) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+
+        return
+    }
+
+    let mut last = String::new();
+
+    println!("{}", debugger.location());
+
+    loop {
+        print!("(hack) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break
+        }
+
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+
+        match line.as_str() {
+            "quit" | "q" => break,
+            "help" | "h" => println!("{}", HELP),
+            _ => match debugger.execute(&line) {
+                Ok(out) if out.is_empty() => (),
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("{}", e),
+            },
+        }
+
+        last = line;
+    }
+}
+
+// On stdio, or one connection to a local port
+fn serve_gdb(debugger: &mut Debugger, gdb: &str) -> io::Result<()> {
+    if gdb == "-" {
+        return gdb::serve(debugger, io::stdin(), io::stdout())
+    }
+
+    let port: u16 = gdb.parse().unwrap_or_else(|_| usage());
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    eprintln!("Listening for gdb on {}", listener.local_addr()?);
+
+    let (stream, _) = listener.accept()?;
+    gdb::serve(debugger, stream.try_clone()?, stream)
+}
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(1);
+}
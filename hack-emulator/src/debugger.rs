@@ -0,0 +1,388 @@
+//! Debugger: Breakpoints, watchpoints and VM backtraces over the machine.
+//!
+//! Commands are read a line at a time:
+//!
+//! - `break LABEL|ADDR`, `delete [LABEL|ADDR]`
+//! - `watch RAM[ADDR]`, where ADDR may be a variable such as `SP`
+//! - `step [N]`, `next`, `continue`
+//! - `reverse-step [N]`, `reverse-continue`
+//! - `last-write RAM[ADDR]`
+//! - `print D|A|M|PC|NAME|RAM[X]|RAM[X..Y]`
+//! - `backtrace`
+//!
+//! `next` steps over a VM `call`, running until it returns. `backtrace`
+//! follows the frames `call` pushes below each function's locals: the
+//! return address, then the caller's LCL, ARG, THIS and THAT.
+//!
+//! Execution is recorded, so `reverse-step` and `reverse-continue` can go
+//! back to the last breakpoint or watched change, and `last-write` finds
+//! the instruction that last wrote an address.
+
+use assembler::Instruction;
+
+use crate::history::History;
+use crate::machine::{Machine, Status, RAM_SIZE, ROM_SIZE};
+use crate::symbols::Symbols;
+
+/// RAM addresses of the VM stack pointer and segment bases.
+const SP: u16 = 0;
+const LCL: u16 = 1;
+const ARG: u16 = 2;
+
+/// Words `call` pushes below the callee's locals.
+const FRAME: u16 = 5;
+
+/// Most frames `backtrace` shows, should the saved LCLs loop.
+const MAX_FRAMES: usize = 64;
+
+pub struct Debugger {
+    pub machine: Machine,
+    pub symbols: Symbols,
+    breakpoints: Vec<u16>,
+    /// Watched RAM addresses, with the value last seen.
+    watches: Vec<(u16, u16)>,
+    history: History,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, symbols: Symbols) -> Self {
+        Debugger { machine, symbols, breakpoints: Vec::new(), watches: Vec::new(), history: History::new() }
+    }
+
+    /// Execute one command line, returning what it prints.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["break" | "b", location] => {
+                let address = self.rom_address(location)?;
+                self.insert_breakpoint(address);
+
+                Ok(format!("Breakpoint at {} ({})", address, self.symbols.describe(address)))
+            },
+            ["delete" | "d"] => {
+                self.breakpoints.clear();
+                Ok(String::from("Deleted all breakpoints"))
+            },
+            ["delete" | "d", location] => {
+                let address = self.rom_address(location)?;
+                self.remove_breakpoint(address);
+                Ok(format!("Deleted breakpoint at {}", address))
+            },
+            ["watch" | "w", ram] => {
+                let address = match self.ram_range(ram)? {
+                    (start, end) if start == end => start,
+                    _ => return Err(format!("Expected RAM[ADDR], not '{}'", ram)),
+                };
+                let value = self.machine.ram[address as usize];
+
+                self.watches.retain(|(a, _)| *a != address);
+                self.watches.push((address, value));
+
+                Ok(format!("Watching RAM[{}] = {}", address, value as i16))
+            },
+            ["step" | "s"] => Ok(self.step(1)),
+            ["step" | "s", n] => Ok(self.step(n.parse().map_err(|_| format!("Expected a count, not '{}'", n))?)),
+            ["next" | "n"] => Ok(self.next()),
+            ["continue" | "c"] => {
+                if !self.machine.halted() {
+                    self.advance();
+                }
+
+                Ok(self.resume(|_| false))
+            },
+            ["reverse-step" | "rs"] => Ok(self.reversed(|d| d.reverse_step(1))),
+            ["reverse-step" | "rs", n] => {
+                let n = n.parse().map_err(|_| format!("Expected a count, not '{}'", n))?;
+                Ok(self.reversed(|d| d.reverse_step(n)))
+            },
+            ["reverse-continue" | "rc"] => Ok(self.reversed(|d| d.reverse_continue())),
+            ["last-write" | "lw", ram] => {
+                let address = match self.ram_range(ram)? {
+                    (start, end) if start == end => start,
+                    _ => return Err(format!("Expected RAM[ADDR], not '{}'", ram)),
+                };
+
+                Ok(self.last_write(address))
+            },
+            ["print" | "p", expression] => self.print(expression),
+            ["backtrace" | "bt"] => Ok(self.backtrace()),
+            [] => Ok(String::new()),
+            [command, ..] => Err(format!("Unknown command '{}'", command)),
+        }
+    }
+
+    /// The instruction at PC, e.g. `14  LOOP+2  D=M`.
+    pub fn location(&self) -> String {
+        self.at(self.machine.pc)
+    }
+
+    fn at(&self, pc: u16) -> String {
+        let word = self.machine.rom[pc as usize];
+        let instruction = match Instruction::decode(word) {
+            Some(instruction) => instruction.to_string(),
+            None => format!("{:016b}", word),
+        };
+
+        format!("{}  {}  {}", pc, self.symbols.describe(pc), instruction)
+    }
+
+    fn step(&mut self, n: u64) -> String {
+        for _ in 0..n {
+            self.advance();
+        }
+
+        let mut out = self.watched();
+        out.push_str(&self.location());
+        out
+    }
+
+    // Step, or over a VM call: an unconditional jump after pushing the
+    // address of the next instruction as the return address
+    fn next(&mut self) -> String {
+        let pc = self.machine.pc;
+        let word = self.machine.rom[pc as usize];
+        let sp = self.machine.ram[SP as usize];
+        let calls = word & 0x8007 == 0x8007 && sp >= FRAME && sp < RAM_SIZE as u16
+            && self.machine.ram[(sp - FRAME) as usize] == pc + 1;
+
+        if !calls {
+            return self.step(1)
+        }
+
+        // The callee returns with SP past its arguments' base, below its frame
+        let base = sp - FRAME + 1;
+
+        self.advance();
+        self.resume(|m| m.pc == pc + 1 && m.ram[SP as usize] <= base)
+    }
+
+    pub(crate) fn insert_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub(crate) fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|b| *b != address);
+    }
+
+    /// Run up to `max_cycles` instructions, stopping at a breakpoint, a
+    /// watched write or `until`, or once halted.
+    pub(crate) fn run_until(&mut self, max_cycles: u64, until: impl Fn(&Machine) -> bool) -> Status {
+        let stops = |d: &Debugger| {
+            let m = &d.machine;
+            until(m) || d.breakpoints.contains(&m.pc) || d.watches.iter().any(|(a, v)| m.ram[*a as usize] != *v)
+        };
+
+        for _ in 0..max_cycles {
+            if stops(self) {
+                return Status::Until
+            }
+
+            if self.machine.halted() {
+                return Status::Halted
+            }
+
+            self.advance();
+        }
+
+        if stops(self) { Status::Until } else { Status::MaxCycles }
+    }
+
+    /// Execute the instruction at PC, recording it to go back to.
+    pub(crate) fn advance(&mut self) {
+        self.history.step(&mut self.machine);
+    }
+
+    /// Record a change to RAM or a register made outside execution.
+    pub(crate) fn edited(&mut self) {
+        self.history.edited(&self.machine);
+    }
+
+    /// The end of the history, if the machine has gone back from it.
+    pub(crate) fn replay_end(&self) -> Option<u64> {
+        Some(self.history.end()).filter(|end| *end > self.machine.cycles)
+    }
+
+    /// Go back `n` instructions, returning false if the history ran out
+    /// first.
+    pub(crate) fn reverse_step(&mut self, n: u64) -> bool {
+        let start = match self.history.start() {
+            Some(start) => start,
+            None => return false,
+        };
+        let cycles = self.machine.cycles.checked_sub(n).filter(|c| *c >= start);
+
+        self.history.rewind(&mut self.machine, cycles.unwrap_or(start));
+        cycles.is_some()
+    }
+
+    /// Go back to the last breakpoint or watched change, returning false
+    /// if the history ran out first.
+    pub(crate) fn reverse_continue(&mut self) -> bool {
+        let start = match self.history.start() {
+            Some(start) => start,
+            None => return false,
+        };
+        let now = self.machine.cycles;
+
+        // The machine stops after a write changes a watched address
+        let addresses: Vec<u16> = self.watches.iter().map(|(a, _)| *a).collect();
+        let changed = self.history.last_change(&addresses, now.saturating_sub(1)).map(|w| w.cycle + 1);
+        let hit = self.history.last_pc(&mut self.machine, &self.breakpoints, changed.unwrap_or(start), now);
+        let cycles = changed.max(hit);
+
+        self.history.rewind(&mut self.machine, cycles.unwrap_or(start));
+        cycles.is_some()
+    }
+
+    // Go back, and report where to
+    fn reversed(&mut self, reverse: impl FnOnce(&mut Debugger) -> bool) -> String {
+        let stopped = reverse(self);
+        let mut out = self.watched();
+
+        if !stopped {
+            out.push_str("No more reverse-execution history\n");
+        } else if self.breakpoints.contains(&self.machine.pc) {
+            out.push_str("Breakpoint\n");
+        }
+
+        out.push_str(&self.location());
+        out
+    }
+
+    fn last_write(&self, address: u16) -> String {
+        match self.history.last_write(address, self.machine.cycles) {
+            Some(w) => format!(
+                "RAM[{}]: {} -> {} at cycle {}\n{}", address, w.old as i16, w.new as i16, w.cycle, self.at(w.pc),
+            ),
+            None => format!("RAM[{}] not written since cycle {}", address, self.history.start().unwrap_or(0)),
+        }
+    }
+
+    // Run to a breakpoint, a watched write or `until`, or until halted
+    fn resume(&mut self, until: impl Fn(&Machine) -> bool) -> String {
+        let status = self.run_until(u64::MAX, until);
+        let mut out = self.watched();
+
+        if status == Status::Halted {
+            out.push_str("Program halted\n");
+        } else if self.breakpoints.contains(&self.machine.pc) {
+            out.push_str("Breakpoint\n");
+        }
+
+        out.push_str(&self.location());
+        out
+    }
+
+    /// Report watched addresses that have changed, and remember their values.
+    pub(crate) fn watched(&mut self) -> String {
+        let mut out = String::new();
+
+        for (address, value) in self.watches.iter_mut() {
+            let new = self.machine.ram[*address as usize];
+
+            if new != *value {
+                out.push_str(&format!("RAM[{}]: {} -> {}\n", address, *value as i16, new as i16));
+                *value = new;
+            }
+        }
+
+        out
+    }
+
+    fn print(&self, expression: &str) -> Result<String, String> {
+        let m = &self.machine;
+        let value = match expression {
+            "A" => m.a,
+            "D" => m.d,
+            "M" => m.m(),
+            "PC" => m.pc,
+            _ if expression.starts_with("RAM[") => {
+                let (start, end) = self.ram_range(expression)?;
+                let lines: Vec<String> = (start..=end)
+                    .map(|a| format!("RAM[{}] = {}", a, m.ram[a as usize] as i16))
+                    .collect();
+
+                return Ok(lines.join("\n"))
+            },
+            _ => {
+                let address = self.ram_address(expression)?;
+                return Ok(format!("{} = RAM[{}] = {}", expression, address, m.ram[address as usize] as i16))
+            },
+        };
+
+        Ok(format!("{} = {}", expression, value as i16))
+    }
+
+    fn backtrace(&self) -> String {
+        let ram = &self.machine.ram;
+        let register = |r: u16| ram[r as usize];
+        let mut frames = vec!((self.machine.pc, register(LCL), register(ARG)));
+        let mut lcl = register(LCL);
+
+        // Each frame's saved LCL is below its own, down to the bootstrap's
+        while lcl >= FRAME && (lcl as usize) < RAM_SIZE && frames.len() < MAX_FRAMES {
+            let (ret, saved_lcl, saved_arg) = (register(lcl - 5), register(lcl - 4), register(lcl - 3));
+
+            if ret as usize >= ROM_SIZE {
+                break
+            }
+
+            frames.push((ret, saved_lcl, saved_arg));
+
+            if saved_lcl >= lcl {
+                break
+            }
+
+            lcl = saved_lcl;
+        }
+
+        let lines: Vec<String> = frames.iter().enumerate().map(|(i, (pc, lcl, arg))| {
+            let function = match self.symbols.function(*pc) {
+                Some((name, 0)) => name.to_string(),
+                Some((name, offset)) => format!("{}+{}", name, offset),
+                None => self.symbols.describe(*pc),
+            };
+
+            format!("#{}  {} at {}  LCL={} ARG={}", i, function, pc, lcl, arg)
+        }).collect();
+
+        lines.join("\n")
+    }
+
+    fn rom_address(&self, text: &str) -> Result<u16, String> {
+        self.symbols.label(text).or_else(|| number(text))
+            .filter(|a| (*a as usize) < ROM_SIZE)
+            .ok_or_else(|| format!("Unknown label or ROM address '{}'", text))
+    }
+
+    fn ram_address(&self, text: &str) -> Result<u16, String> {
+        self.symbols.variable(text).or_else(|| number(text))
+            .filter(|a| (*a as usize) < RAM_SIZE)
+            .ok_or_else(|| format!("Unknown variable or RAM address '{}'", text))
+    }
+
+    // RAM[X] or RAM[X..Y], as the first and last addresses, inclusive
+    fn ram_range(&self, text: &str) -> Result<(u16, u16), String> {
+        let inside = text.strip_prefix("RAM[").and_then(|t| t.strip_suffix(']'))
+            .ok_or_else(|| format!("Expected RAM[ADDR] or RAM[X..Y], not '{}'", text))?;
+
+        match inside.split_once("..") {
+            Some((start, end)) => {
+                let (start, end) = (self.ram_address(start)?, self.ram_address(end)?);
+                if start <= end { Ok((start, end)) } else { Err(format!("Empty range '{}'", text)) }
+            },
+            None => self.ram_address(inside).map(|a| (a, a)),
+        }
+    }
+}
+
+// Decimal, or hex with 0x
+fn number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
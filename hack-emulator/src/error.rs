@@ -0,0 +1,117 @@
+//! Error: Failures loading programs and scripts, and failed comparisons.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use assembler::AssemblyError;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    Assembly(Box<AssemblyError>),
+    UnrecognisedWord,
+    ProgramTooLarge,
+    InvalidKeyboardScript,
+    UnrecognisedKey,
+    InvalidScript,
+    UnboundedRepeat,
+    /// The line expected from the `.cmp` file.
+    ComparisonFailure(String),
+}
+
+/// Failure to load a program, located at the offending line.
+///
+/// `line` is 1-based. Errors that have no line (e.g. failing to open the
+/// file) use line 0.
+#[derive(Debug)]
+pub struct EmulatorError {
+    pub kind: ErrorKind,
+    pub file: Option<String>,
+    pub line: usize,
+    pub text: String,
+}
+
+impl EmulatorError {
+    pub fn new(kind: ErrorKind, line: usize, text: &str) -> Self {
+        EmulatorError {
+            kind,
+            file: None,
+            line,
+            text: String::from(text),
+        }
+    }
+
+    pub fn io(error: io::Error) -> Self {
+        EmulatorError::new(ErrorKind::Io(error), 0, "")
+    }
+
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| String::from(file));
+        self
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(error: io::Error) -> Self {
+        EmulatorError::io(error)
+    }
+}
+
+impl From<AssemblyError> for EmulatorError {
+    fn from(error: AssemblyError) -> Self {
+        EmulatorError::new(ErrorKind::Assembly(Box::new(error)), 0, "")
+    }
+}
+
+impl error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            ErrorKind::Assembly(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Located in the assembly source
+        if let ErrorKind::Assembly(e) = &self.kind {
+            return write!(f, "{}", e)
+        }
+
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+
+        write!(f, " error: {}", self.kind)?;
+
+        if !self.text.is_empty() {
+            write!(f, " '{}'", self.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            Assembly(e) => write!(f, "{}", e),
+            UnrecognisedWord => write!(f, "Expected 16 '0'/'1' characters"),
+            ProgramTooLarge => write!(f, "Program longer than 32K words of ROM"),
+            InvalidKeyboardScript => write!(f, "Expected PRESS..RELEASE KEY, or frame CYCLES"),
+            UnrecognisedKey => write!(f, "Unrecognised key"),
+            InvalidScript => write!(f, "Invalid test script command"),
+            UnboundedRepeat => write!(f, "repeat without a count runs forever, which is not supported"),
+            ComparisonFailure(expected) => write!(f, "Comparison failure, expected '{}', got", expected),
+        }
+    }
+}
@@ -0,0 +1,587 @@
+//! Gdb: GDB remote serial protocol stub for the debugger's machine.
+//!
+//! The stub serves one client over any byte stream, e.g. a TCP connection
+//! or stdio. Registers are numbered 0 A, 1 D and 2 PC, each 16 bits.
+//! Memory is byte addressed and little-endian, with RAM[n] at 2n and ROM[n]
+//! at `ROM_BASE` + 2n. ROM can be read but not written. PC and breakpoint
+//! addresses are ROM addresses in words, as in the program.
+//!
+//! Packets supported: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `c`, `s`, `vCont`,
+//! `bc` and `bs` (reverse continue and step), `Z0`/`Z1` and `z0`/`z1`
+//! breakpoints, `qSupported`, `qXfer:features:read`, `qRcmd` (debugger
+//! commands, e.g. `monitor backtrace`), `QStartNoAckMode`, `D` and `k`.
+//! Others get the empty reply. ^C interrupts a `continue`. Having gone back,
+//! the machine stops at either end of the history with `replaylog` in the
+//! stop reply.
+//!
+//! Packets received are acknowledged, but acknowledgements from the client
+//! are not awaited: streams are assumed reliable.
+
+use std::io::{self, prelude::*};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::debugger::Debugger;
+use crate::machine::{Status, RAM_SIZE, ROM_SIZE};
+
+/// Byte address of ROM[0].
+pub const ROM_BASE: u32 = 0x10000;
+
+/// Instructions run between checks for an interrupt.
+const SLICE: u64 = 100_000;
+
+const INTERRUPT: u8 = 0x03;
+
+/// Signals in stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Most bytes of console output per `O` packet.
+const CONSOLE_CHUNK: usize = 256;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack.cpu">
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16" regnum="1"/>
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+/// Serve one client until it detaches or kills the session, or the input
+/// ends.
+pub fn serve(debugger: &mut Debugger, mut input: impl Read + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    // Read on another thread, so an interrupt can arrive while running
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+
+        while let Ok(n @ 1..) = input.read(&mut buffer) {
+            if buffer[..n].iter().any(|b| sender.send(*b).is_err()) {
+                break
+            }
+        }
+    });
+
+    let mut stub = Stub { debugger, input: receiver, output, ack: true };
+
+    while let Some(packet) = stub.receive()? {
+        match packet.as_str() {
+            "k" => break,
+            _ if packet.starts_with('D') => {
+                stub.send("OK")?;
+                break
+            },
+            _ => {
+                let reply = stub.reply(&packet)?;
+                stub.send(&reply)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+struct Stub<'a, W> {
+    debugger: &'a mut Debugger,
+    input: Receiver<u8>,
+    output: W,
+    /// Acknowledge packets, until the client asks for no-ack mode.
+    ack: bool,
+}
+
+impl<W: Write> Stub<'_, W> {
+    // The next packet's data, skipping acknowledgements and stray bytes
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let input = &self.input;
+            let byte = || input.recv().ok();
+
+            loop {
+                match byte() {
+                    Some(b'$') => break,
+                    Some(_) => (),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut data = Vec::new();
+
+            loop {
+                match byte() {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+
+            let sum = match (byte(), byte()) {
+                (Some(high), Some(low)) => decode_hex(&[high, low]),
+                _ => return Ok(None),
+            };
+
+            if !self.ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+            }
+
+            if sum == Some(vec![checksum(&data)]) {
+                self.output.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+            }
+
+            self.output.write_all(b"-")?;
+            self.output.flush()?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.output, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+        self.output.flush()
+    }
+
+    // Print to the client's console
+    fn console(&mut self, text: &str) -> io::Result<()> {
+        for chunk in text.as_bytes().chunks(CONSOLE_CHUNK) {
+            self.send(&format!("O{}", encode_hex(chunk)))?;
+        }
+
+        Ok(())
+    }
+
+    fn reply(&mut self, packet: &str) -> io::Result<String> {
+        let args = packet.get(1..).unwrap_or("");
+
+        let reply = match packet.chars().next() {
+            Some('?') => Some(stop(SIGTRAP)),
+            Some('g') => Some(self.registers().iter().map(|r| encode_hex(&r.to_le_bytes())).collect()),
+            Some('G') => self.write_registers(args),
+            Some('p') => self.read_register(args),
+            Some('P') => self.write_register(args),
+            Some('m') => self.read_memory(args),
+            Some('M') => self.write_memory(args),
+            Some('c') => return self.resume(args),
+            Some('s') => return self.step(args),
+            Some('b') => return self.reverse(args),
+            Some('Z') => self.breakpoint(args, true),
+            Some('z') => self.breakpoint(args, false),
+            Some('H') | Some('T') => Some(String::from("OK")),
+            Some('v') => return self.v(packet),
+            Some('q') | Some('Q') => return self.query(packet),
+            _ => Some(String::new()),
+        };
+
+        Ok(reply.unwrap_or_else(|| String::from("E01")))
+    }
+
+    fn registers(&self) -> [u16; 3] {
+        let m = &self.debugger.machine;
+        [m.a, m.d, m.pc]
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) -> Option<String> {
+        let m = &mut self.debugger.machine;
+
+        match n {
+            0 => m.a = value,
+            1 => m.d = value,
+            2 => m.pc = value,
+            _ => return None,
+        }
+
+        self.debugger.edited();
+        Some(String::from("OK"))
+    }
+
+    // p N
+    fn read_register(&self, args: &str) -> Option<String> {
+        let n = usize::from_str_radix(args, 16).ok()?;
+        let value = self.registers().get(n).copied()?;
+
+        Some(encode_hex(&value.to_le_bytes()))
+    }
+
+    // P N=VALUE
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, value) = args.split_once('=')?;
+        self.set_register(usize::from_str_radix(n, 16).ok()?, word(value)?)
+    }
+
+    // G VALUES
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = decode_hex(args.as_bytes()).filter(|b| b.len() == 6)?;
+
+        for (n, value) in bytes.chunks(2).enumerate() {
+            self.set_register(n, u16::from_le_bytes([value[0], value[1]]))?;
+        }
+
+        Some(String::from("OK"))
+    }
+
+    // m ADDR,LENGTH
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = address_length(args)?;
+        let bytes: Vec<u8> = (address..address.saturating_add(length))
+            .map_while(|a| self.read_byte(a))
+            .collect();
+
+        if bytes.is_empty() && length > 0 { None } else { Some(encode_hex(&bytes)) }
+    }
+
+    // M ADDR,LENGTH:BYTES
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, bytes) = args.split_once(':')?;
+        let (address, length) = address_length(range)?;
+        let bytes = decode_hex(bytes.as_bytes()).filter(|b| b.len() == length as usize)?;
+
+        // ROM is read-only, as the history replays it as it is now
+        let ram = |a| matches!(memory(a), Some((Memory::Ram, _)));
+        if !(address..).take(bytes.len()).all(ram) {
+            return None
+        }
+
+        for (a, byte) in (address..).zip(bytes) {
+            self.write_byte(a, byte);
+        }
+
+        self.debugger.edited();
+        Some(String::from("OK"))
+    }
+
+    fn read_byte(&self, address: u32) -> Option<u8> {
+        let m = &self.debugger.machine;
+        let word = match memory(address)? {
+            (Memory::Ram, index) => m.ram[index],
+            (Memory::Rom, index) => m.rom[index],
+        };
+
+        Some(word.to_le_bytes()[(address % 2) as usize])
+    }
+
+    // A byte of RAM
+    fn write_byte(&mut self, address: u32, byte: u8) {
+        let word = &mut self.debugger.machine.ram[(address / 2) as usize];
+        let mut bytes = word.to_le_bytes();
+
+        bytes[(address % 2) as usize] = byte;
+        *word = u16::from_le_bytes(bytes);
+    }
+
+    // Z/z TYPE,ADDR,KIND: software and hardware breakpoints are alike
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok().filter(|a| (*a as usize) < ROM_SIZE)?;
+
+        if kind != "0" && kind != "1" {
+            return Some(String::new())
+        }
+
+        if insert {
+            self.debugger.insert_breakpoint(address);
+        } else {
+            self.debugger.remove_breakpoint(address);
+        }
+
+        Some(String::from("OK"))
+    }
+
+    // Resume at ADDR, if given
+    fn jump(&mut self, args: &str) -> bool {
+        match args {
+            "" => true,
+            _ => match u16::from_str_radix(args, 16) {
+                Ok(address) if (address as usize) < ROM_SIZE => {
+                    self.debugger.machine.pc = address;
+                    self.debugger.edited();
+                    true
+                },
+                _ => false,
+            },
+        }
+    }
+
+    // s [ADDR]
+    fn step(&mut self, args: &str) -> io::Result<String> {
+        if !self.jump(args) {
+            return Ok(String::from("E01"))
+        }
+
+        self.debugger.advance();
+        self.stopped(Status::Until)
+    }
+
+    // bs or bc: back a step, or to the last breakpoint
+    fn reverse(&mut self, args: &str) -> io::Result<String> {
+        let stopped = match args {
+            "s" => self.debugger.reverse_step(1),
+            "c" => self.debugger.reverse_continue(),
+            _ => return Ok(String::new()),
+        };
+
+        let reply = self.stopped(Status::Until)?;
+        Ok(if stopped { reply } else { replaylog(&reply, "begin") })
+    }
+
+    // c [ADDR]: run in slices, to check for an interrupt between them.
+    // Having gone back, stop again at the end of the history.
+    fn resume(&mut self, args: &str) -> io::Result<String> {
+        if !self.jump(args) {
+            return Ok(String::from("E01"))
+        }
+
+        let end = self.debugger.replay_end();
+
+        if !self.debugger.machine.halted() {
+            self.debugger.advance();
+        }
+
+        loop {
+            let left = end.map(|end| end.saturating_sub(self.debugger.machine.cycles));
+
+            match self.debugger.run_until(left.map_or(SLICE, |left| left.min(SLICE)), |_| false) {
+                Status::MaxCycles if end == Some(self.debugger.machine.cycles) => {
+                    let reply = self.stopped(Status::MaxCycles)?;
+                    return Ok(replaylog(&reply, "end"))
+                },
+                Status::MaxCycles if self.interrupted() => {
+                    self.stopped(Status::MaxCycles)?;
+                    return Ok(stop(SIGINT))
+                },
+                Status::MaxCycles => (),
+                status => return self.stopped(status),
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        loop {
+            match self.input.try_recv() {
+                Ok(INTERRUPT) | Err(TryRecvError::Disconnected) => return true,
+                Ok(_) => (),
+                Err(TryRecvError::Empty) => return false,
+            }
+        }
+    }
+
+    // Report watched changes, and a halt, on the console
+    fn stopped(&mut self, status: Status) -> io::Result<String> {
+        let mut out = self.debugger.watched();
+
+        if status == Status::Halted {
+            out.push_str("Program halted\n");
+        }
+
+        self.console(&out)?;
+        Ok(stop(SIGTRAP))
+    }
+
+    fn v(&mut self, packet: &str) -> io::Result<String> {
+        if packet == "vCont?" {
+            return Ok(String::from("vCont;c;C;s;S"))
+        }
+
+        // The first action applies, there being one thread
+        match packet.strip_prefix("vCont;").and_then(|a| a.chars().next()) {
+            Some('c') | Some('C') => self.resume(""),
+            Some('s') | Some('S') => self.step(""),
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> io::Result<String> {
+        let reply = match packet.split([':', ',']).next().unwrap_or("") {
+            "qSupported" => String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+"),
+            "QStartNoAckMode" => {
+                self.ack = false;
+                String::from("OK")
+            },
+            "qXfer" => features(packet).unwrap_or_else(|| String::from("E01")),
+            "qRcmd" => return self.monitor(packet.strip_prefix("qRcmd,").unwrap_or("")),
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    // qRcmd,COMMAND: a debugger command, printed on the console
+    fn monitor(&mut self, command: &str) -> io::Result<String> {
+        let command = match decode_hex(command.as_bytes()) {
+            Some(command) => String::from_utf8_lossy(&command).into_owned(),
+            None => return Ok(String::from("E01")),
+        };
+
+        let mut out = match self.debugger.execute(&command) {
+            Ok(out) | Err(out) => out,
+        };
+
+        if !out.is_empty() {
+            out.push('\n');
+            self.console(&out)?;
+        }
+
+        Ok(String::from("OK"))
+    }
+}
+
+enum Memory {
+    Ram,
+    Rom,
+}
+
+// The memory, and index of the word, holding a byte address
+fn memory(address: u32) -> Option<(Memory, usize)> {
+    if ((address / 2) as usize) < RAM_SIZE {
+        return Some((Memory::Ram, (address / 2) as usize))
+    }
+
+    let index = (address.checked_sub(ROM_BASE)? / 2) as usize;
+    if index < ROM_SIZE { Some((Memory::Rom, index)) } else { None }
+}
+
+// qXfer:features:read:target.xml:OFFSET,LENGTH
+fn features(packet: &str) -> Option<String> {
+    let range = packet.strip_prefix("qXfer:features:read:target.xml:")?;
+    let (offset, length) = address_length(range)?;
+    let xml = TARGET_XML.get(offset as usize..)?;
+
+    match xml.get(..length as usize) {
+        Some(part) if part.len() < xml.len() => Some(format!("m{}", part)),
+        _ => Some(format!("l{}", xml)),
+    }
+}
+
+fn stop(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+// A stop reply at the start or end of the history, e.g. `S05` at the
+// start is `T05replaylog:begin;`
+fn replaylog(reply: &str, log: &str) -> String {
+    format!("T{}replaylog:{};", &reply[1..], log)
+}
+
+// ADDR,LENGTH in hex
+fn address_length(args: &str) -> Option<(u32, u32)> {
+    let (address, length) = args.split_once(',')?;
+    Some((u32::from_str_radix(address, 16).ok()?, u32::from_str_radix(length, 16).ok()?))
+}
+
+// A register value, in little-endian hex
+fn word(hex: &str) -> Option<u16> {
+    let bytes = decode_hex(hex.as_bytes()).filter(|b| b.len() == 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Sum of a packet's data, modulo 256.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None
+    }
+
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::machine::Machine;
+    use crate::symbols::Symbols;
+
+    // Counts RAM[16] down from 7, then halts at END (9)
+    const COUNTDOWN: &str = "@7\nD=A\n@count\nM=D\n(LOOP)\n@count\nM=M-1\nD=M\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP\n";
+
+    // Serve `packets`, returning each packet sent back
+    fn session(packets: &[&str]) -> Vec<String> {
+        let mut machine = Machine::new();
+        machine.load(&assembler::assemble_str(COUNTDOWN).unwrap()).unwrap();
+
+        let mut debugger = Debugger::new(machine, Symbols::new());
+        let input: String = packets.iter().map(|p| format!("${}#{:02x}", p, checksum(p.as_bytes()))).collect();
+        let mut output = Vec::new();
+
+        serve(&mut debugger, Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        String::from_utf8(output).unwrap().split('$').skip(1)
+            .map(|p| p[..p.find('#').unwrap()].to_string())
+            .collect()
+    }
+
+    fn console(text: &str) -> String {
+        format!("O{}", encode_hex(text.as_bytes()))
+    }
+
+    #[test]
+    fn test_reverse_to_start_of_history() {
+        let watch = format!("qRcmd,{}", encode_hex(b"watch RAM[16]"));
+        let replies = session(&["bs", "s", "s", "s", "s", &watch, "bc", "bs", "k"]);
+
+        // Back past the write of 7, to where RAM[16] was 0
+        assert_eq!(replies, vec!(
+            String::from("T05replaylog:begin;"),
+            String::from("S05"), String::from("S05"), String::from("S05"), String::from("S05"),
+            console("Watching RAM[16] = 7\n"), String::from("OK"),
+            console("RAM[16]: 7 -> 0\n"), String::from("T05replaylog:begin;"),
+            String::from("T05replaylog:begin;"),
+        ));
+    }
+
+    #[test]
+    fn test_rom_is_read_only() {
+        let replies = session(&["Mfffe,4:01000200", "m10000,2", "M20,2:0100", "k"]);
+
+        assert_eq!(replies, vec!("E01", "0700", "OK"));
+    }
+
+    #[test]
+    fn test_continue_to_end_of_history() {
+        let replies = session(&["s", "s", "s", "s", "bs", "bs", "c", "p2", "c", "k"]);
+
+        assert_eq!(replies[4..], [
+            String::from("S05"), String::from("S05"), String::from("T05replaylog:end;"), String::from("0400"),
+            console("Program halted\n"), String::from("S05"),
+        ]);
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b"qSupported"), 0x37);
+        assert_eq!(checksum(b""), 0);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(encode_hex(&[0x01, 0xab]), "01ab");
+        assert_eq!(decode_hex(b"01ab"), Some(vec!(0x01, 0xab)));
+        assert_eq!(decode_hex(b"1ab"), None);
+        assert_eq!(word("0001"), Some(0x0100));
+    }
+
+    #[test]
+    fn test_features_in_parts() {
+        let first = features("qXfer:features:read:target.xml:0,10").unwrap();
+        let rest = features("qXfer:features:read:target.xml:10,1000").unwrap();
+
+        assert_eq!(first, format!("m{}", &TARGET_XML[..16]));
+        assert_eq!(rest, format!("l{}", &TARGET_XML[16..]));
+    }
+}
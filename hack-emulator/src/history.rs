@@ -0,0 +1,264 @@
+//! History: Snapshots and a RAM write log, for running the machine backward.
+//!
+//! A snapshot of the registers, RAM and keyboard is taken every `INTERVAL`
+//! cycles, and every RAM write is logged. The machine goes back to a cycle
+//! by restoring the snapshot before it and executing forward again, which
+//! is deterministic: the keyboard only depends on the cycle.
+//!
+//! Executing from a cycle before the end of the history forgets the rest,
+//! as the machine may since have been changed. Only the last
+//! `MAX_SNAPSHOTS` intervals are kept.
+//!
+//! That bounds the memory used, but not tightly: each snapshot holds 64 KB
+//! of RAM, and each write is logged in 16 bytes. A program writing RAM on
+//! every cycle keeps 10 million writes, or about 160 MB, on top of 6.4 MB
+//! of snapshots.
+
+use crate::keyboard::Keyboard;
+use crate::machine::Machine;
+
+/// Cycles between snapshots.
+pub const INTERVAL: u64 = 100_000;
+
+const MAX_SNAPSHOTS: usize = 100;
+
+struct Snapshot {
+    cycles: u64,
+    a: u16,
+    d: u16,
+    pc: u16,
+    ram: Vec<u16>,
+    keyboard: Keyboard,
+}
+
+impl Snapshot {
+    fn new(machine: &Machine) -> Self {
+        Snapshot {
+            cycles: machine.cycles,
+            a: machine.a,
+            d: machine.d,
+            pc: machine.pc,
+            ram: machine.ram.clone(),
+            keyboard: machine.keyboard.clone(),
+        }
+    }
+
+    fn restore(&self, machine: &mut Machine) {
+        machine.cycles = self.cycles;
+        machine.a = self.a;
+        machine.d = self.d;
+        machine.pc = self.pc;
+        machine.ram.copy_from_slice(&self.ram);
+        machine.keyboard = self.keyboard.clone();
+    }
+}
+
+/// A RAM write, by the instruction at `pc` in `cycle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Write {
+    pub cycle: u64,
+    pub pc: u16,
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Default)]
+pub struct History {
+    /// Sorted by cycle, as are the writes.
+    snapshots: Vec<Snapshot>,
+    writes: Vec<Write>,
+    /// The cycle after the last recorded.
+    end: u64,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// The earliest cycle the machine can go back to, if any.
+    pub fn start(&self) -> Option<u64> {
+        self.snapshots.first().map(|s| s.cycles)
+    }
+
+    /// The cycle after the last recorded.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Execute the instruction at PC, recording it.
+    pub fn step(&mut self, machine: &mut Machine) {
+        if machine.cycles != self.end {
+            self.truncate(machine.cycles);
+            self.snapshot(machine);
+        } else if machine.cycles.is_multiple_of(INTERVAL) || self.snapshots.is_empty() {
+            self.snapshot(machine);
+        }
+
+        let (cycle, pc) = (machine.cycles, machine.pc);
+        let write = machine.writes().map(|address| (address, machine.ram[address as usize]));
+
+        machine.step();
+
+        if let Some((address, old)) = write {
+            self.writes.push(Write { cycle, pc, address, old, new: machine.ram[address as usize] });
+        }
+
+        self.end = machine.cycles;
+    }
+
+    /// Record the machine as it is now, e.g. after RAM or a register is
+    /// changed, forgetting any later history.
+    pub fn edited(&mut self, machine: &Machine) {
+        if !self.snapshots.is_empty() {
+            self.truncate(machine.cycles);
+            self.snapshot(machine);
+            self.end = machine.cycles;
+        }
+    }
+
+    /// Put the machine back as it was at `cycles`, no earlier than the
+    /// start of the history, returning the cycle it is now at.
+    pub fn rewind(&self, machine: &mut Machine, cycles: u64) -> u64 {
+        let i = self.snapshots.iter().rposition(|s| s.cycles <= cycles).unwrap_or(0);
+
+        if let Some(snapshot) = self.snapshots.get(i) {
+            snapshot.restore(machine);
+
+            while machine.cycles < cycles {
+                machine.step();
+            }
+        }
+
+        machine.cycles
+    }
+
+    /// The last cycle in `after..before` that began with PC at one of
+    /// `pcs`, found by replaying the intervals back from `before`. The
+    /// machine is left wherever the replay stopped.
+    pub fn last_pc(&self, machine: &mut Machine, pcs: &[u16], after: u64, before: u64) -> Option<u64> {
+        let mut end = before;
+
+        if pcs.is_empty() {
+            return None
+        }
+
+        for snapshot in self.snapshots.iter().rev().filter(|s| s.cycles < before) {
+            let mut last = None;
+
+            snapshot.restore(machine);
+
+            while machine.cycles < end {
+                if machine.cycles >= after && pcs.contains(&machine.pc) {
+                    last = Some(machine.cycles);
+                }
+
+                machine.step();
+            }
+
+            if last.is_some() || snapshot.cycles <= after {
+                return last
+            }
+
+            end = snapshot.cycles;
+        }
+
+        None
+    }
+
+    /// The last write to `address` before `before`.
+    pub fn last_write(&self, address: u16, before: u64) -> Option<&Write> {
+        self.writes.iter().rev().filter(|w| w.cycle < before).find(|w| w.address == address)
+    }
+
+    /// The last write before `before` that changed one of `addresses`.
+    pub fn last_change(&self, addresses: &[u16], before: u64) -> Option<&Write> {
+        self.writes.iter().rev()
+            .filter(|w| w.cycle < before)
+            .find(|w| w.old != w.new && addresses.contains(&w.address))
+    }
+
+    fn snapshot(&mut self, machine: &Machine) {
+        self.snapshots.push(Snapshot::new(machine));
+
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.remove(0);
+
+            let start = self.snapshots[0].cycles;
+            let n = self.writes.iter().take_while(|w| w.cycle < start).count();
+            self.writes.drain(..n);
+        }
+    }
+
+    // Forget from `cycles` on
+    fn truncate(&mut self, cycles: u64) {
+        self.snapshots.retain(|s| s.cycles < cycles);
+        self.writes.retain(|w| w.cycle < cycles);
+        self.end = cycles;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RAM[16] counts up forever
+    fn machine() -> Machine {
+        let mut machine = Machine::new();
+        machine.load(&[16, 0xfdc8, 0, 0xea87]).unwrap(); // @16, M=M+1, @0, 0;JMP
+        machine
+    }
+
+    fn record(history: &mut History, machine: &mut Machine, cycles: u64) {
+        for _ in 0..cycles {
+            history.step(machine);
+        }
+    }
+
+    #[test]
+    fn test_rewind_across_snapshots() {
+        let (mut history, mut machine) = (History::new(), machine());
+        record(&mut history, &mut machine, 2 * INTERVAL + 10);
+
+        assert_eq!(history.rewind(&mut machine, INTERVAL + 6), INTERVAL + 6);
+        assert_eq!((machine.pc, machine.ram[16]), (2, (INTERVAL / 4 + 2) as u16));
+    }
+
+    #[test]
+    fn test_writes_are_logged() {
+        let (mut history, mut machine) = (History::new(), machine());
+        record(&mut history, &mut machine, 10);
+
+        assert_eq!(history.last_write(16, 10), Some(&Write { cycle: 9, pc: 1, address: 16, old: 2, new: 3 }));
+        assert_eq!(history.last_write(16, 9).map(|w| w.cycle), Some(5));
+        assert_eq!(history.last_write(17, 10), None);
+    }
+
+    #[test]
+    fn test_executing_after_rewind_forgets_the_rest() {
+        let (mut history, mut machine) = (History::new(), machine());
+        record(&mut history, &mut machine, 10);
+        history.rewind(&mut machine, 4);
+
+        machine.ram[16] = 100;
+        history.edited(&machine);
+        record(&mut history, &mut machine, 2);
+
+        assert_eq!(history.last_write(16, 10).map(|w| (w.cycle, w.new)), Some((5, 101)));
+
+        history.rewind(&mut machine, 5);
+        assert_eq!(machine.ram[16], 100);
+    }
+
+    #[test]
+    fn test_last_pc() {
+        let (mut history, mut machine) = (History::new(), machine());
+        record(&mut history, &mut machine, INTERVAL + 10);
+
+        assert_eq!(history.last_pc(&mut machine, &[3], 0, INTERVAL + 10), Some(INTERVAL + 7));
+        assert_eq!(history.last_pc(&mut machine, &[3], 0, INTERVAL + 1), Some(INTERVAL - 1));
+        assert_eq!(history.last_pc(&mut machine, &[3], INTERVAL + 8, INTERVAL + 10), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Keyboard: Scripted key presses fed into the KBD memory map.
+//!
+//! A script has one key per line, held from a press time to a release time,
+//! in cycles or in frames with an `f` suffix:
+//!
+//! ```text
+//! // PRESS..RELEASE KEY
+//! frame 10000          // cycles per frame, from here on
+//! 1000..1500 65        // key code
+//! 2f..3f ENTER         // special key name
+//! 5f..6f "hi\n"        // string, one key after another
+//! ```
+//!
+//! Each further key of a string is pressed after a gap as long as the hold.
+//! While keys overlap, KBD holds the most recently pressed.
+
+use std::str::FromStr;
+
+use crate::error::{EmulatorError, ErrorKind};
+
+/// Cycles per frame until a script sets its own.
+pub const FRAME_CYCLES: u64 = 10_000;
+
+static SPECIAL_KEYS: &[(&str, u16)] = &[
+    ("NEWLINE", 128), ("ENTER", 128), ("BACKSPACE", 129),
+    ("LEFT", 130), ("UP", 131), ("RIGHT", 132), ("DOWN", 133),
+    ("HOME", 134), ("END", 135), ("PAGEUP", 136), ("PAGEDOWN", 137),
+    ("INSERT", 138), ("DELETE", 139), ("ESC", 140),
+    ("F1", 141), ("F2", 142), ("F3", 143), ("F4", 144), ("F5", 145), ("F6", 146),
+    ("F7", 147), ("F8", 148), ("F9", 149), ("F10", 150), ("F11", 151), ("F12", 152),
+];
+
+/// Hack key code for a special key name, e.g. `LEFT` is 130.
+pub fn key_code(name: &str) -> Option<u16> {
+    SPECIAL_KEYS.iter().find(|(n, _)| *n == name).map(|(_, code)| *code)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Event {
+    cycle: u64,
+    key: u16,
+    press: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Keyboard {
+    events: Vec<Event>,
+    next: usize,
+    held: Vec<u16>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard::default()
+    }
+
+    /// Hold `key` from cycle `press` until cycle `release`.
+    pub fn press(&mut self, key: u16, press: u64, release: u64) {
+        self.insert(Event { cycle: press, key, press: true });
+        self.insert(Event { cycle: release, key, press: false });
+    }
+
+    // Keep the events due sorted, after any at the same time. Releases
+    // sort first, so a key pressed as another is released wins.
+    fn insert(&mut self, event: Event) {
+        let order = |e: &Event| (e.cycle, e.press);
+        let i = self.next + self.events[self.next..].partition_point(|e| order(e) <= order(&event));
+        self.events.insert(i, event);
+    }
+
+    /// Apply the events due by `cycles`, returning the new KBD value if
+    /// any were.
+    pub fn update(&mut self, cycles: u64) -> Option<u16> {
+        let mut changed = false;
+
+        while let Some(event) = self.events.get(self.next).copied() {
+            if event.cycle > cycles {
+                break
+            }
+
+            if event.press {
+                self.held.push(event.key);
+            } else if let Some(i) = self.held.iter().rposition(|k| *k == event.key) {
+                self.held.remove(i);
+            }
+
+            self.next += 1;
+            changed = true;
+        }
+
+        if changed { Some(self.held.last().copied().unwrap_or(0)) } else { None }
+    }
+
+    /// Have all keys been pressed and released?
+    pub fn is_done(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    /// Start the script again from cycle 0.
+    pub fn rewind(&mut self) {
+        self.next = 0;
+        self.held.clear();
+    }
+}
+
+impl FromStr for Keyboard {
+    type Err = EmulatorError;
+
+    fn from_str(script: &str) -> Result<Self, Self::Err> {
+        let mut keyboard = Keyboard::new();
+        let mut frame = FRAME_CYCLES;
+
+        for (i, line) in script.lines().enumerate() {
+            let error = |kind, text: &str| EmulatorError::new(kind, i + 1, text);
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue
+            }
+
+            let (time, keys) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| error(ErrorKind::InvalidKeyboardScript, line))?;
+            let keys = keys.trim();
+
+            if time == "frame" {
+                frame = keys.parse().ok().filter(|f| *f > 0)
+                    .ok_or_else(|| error(ErrorKind::InvalidKeyboardScript, keys))?;
+                continue
+            }
+
+            let (press, release) = time.split_once("..")
+                .and_then(|(p, r)| Some((cycle(p, frame)?, cycle(r, frame)?)))
+                .filter(|(p, r)| p < r)
+                .ok_or_else(|| error(ErrorKind::InvalidKeyboardScript, time))?;
+
+            let keys = parse_keys(keys).ok_or_else(|| error(ErrorKind::UnrecognisedKey, keys))?;
+            let hold = release - press;
+
+            for (n, key) in keys.into_iter().enumerate() {
+                let start = hold.checked_mul(2 * n as u64).and_then(|gap| press.checked_add(gap));
+                let (start, release) = start.and_then(|s| Some((s, s.checked_add(hold)?)))
+                    .ok_or_else(|| error(ErrorKind::InvalidKeyboardScript, time))?;
+
+                keyboard.press(key, start, release);
+            }
+        }
+
+        Ok(keyboard)
+    }
+}
+
+// CYCLE, or FRAMEf
+fn cycle(time: &str, frame: u64) -> Option<u64> {
+    match time.strip_suffix('f') {
+        Some(frames) => frames.parse::<u64>().ok()?.checked_mul(frame),
+        None => time.parse().ok(),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = quoted,
+            '"' => quoted = !quoted,
+            '/' if !quoted && line[i..].starts_with("//") => return &line[..i],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+// A key code, a special key name, or a quoted string of keys
+fn parse_keys(keys: &str) -> Option<Vec<u16>> {
+    if let Some(string) = keys.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+        return parse_string(string)
+    }
+
+    match keys.parse::<u16>() {
+        Ok(code) => Some(vec![code]),
+        Err(_) => key_code(keys).map(|code| vec![code]),
+    }
+}
+
+// Characters, with escapes \n \b \\ \" and {NAME} for special keys
+fn parse_string(string: &str) -> Option<Vec<u16>> {
+    let mut keys = Vec::new();
+    let mut chars = string.chars();
+
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\\' => match chars.next()? {
+                'n' => key_code("NEWLINE")?,
+                'b' => key_code("BACKSPACE")?,
+                c @ ('\\' | '"' | '{') => c as u16,
+                _ => return None,
+            },
+            '{' => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                key_code(&name)?
+            },
+            ' '..='~' => c as u16,
+            _ => return None,
+        };
+
+        keys.push(key);
+    }
+
+    Some(keys)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keyboard: &mut Keyboard, cycles: &[u64]) -> Vec<Option<u16>> {
+        cycles.iter().map(|c| keyboard.update(*c)).collect()
+    }
+
+    #[test]
+    fn test_press_and_release() {
+        let mut keyboard: Keyboard = "10..20 65\n".parse().unwrap();
+
+        assert_eq!(keys(&mut keyboard, &[0, 10, 15, 20]), vec!(None, Some(65), None, Some(0)));
+    }
+
+    #[test]
+    fn test_frames_and_special_keys() {
+        let mut keyboard: Keyboard = "frame 100\n1f..2f LEFT // arrow\n".parse().unwrap();
+
+        assert_eq!(keys(&mut keyboard, &[99, 100, 200]), vec!(None, Some(130), Some(0)));
+    }
+
+    #[test]
+    fn test_string_keys_follow_each_other() {
+        let mut keyboard: Keyboard = r#"0..5 "a{UP}\n""#.parse().unwrap();
+
+        assert_eq!(keys(&mut keyboard, &[0, 5, 10, 15, 20, 25]), vec!(
+            Some(97), Some(0), Some(131), Some(0), Some(128), Some(0),
+        ));
+    }
+
+    #[test]
+    fn test_overlapping_keys() {
+        let mut keyboard: Keyboard = "0..30 65\n10..20 66\n".parse().unwrap();
+
+        assert_eq!(keys(&mut keyboard, &[0, 10, 20, 30]), vec!(Some(65), Some(66), Some(65), Some(0)));
+    }
+
+    #[test]
+    fn test_keys_pressed_out_of_order() {
+        let mut keyboard = Keyboard::new();
+        keyboard.press(66, 20, 30);
+        keyboard.press(65, 0, 20);
+
+        assert_eq!(keys(&mut keyboard, &[0, 20, 30]), vec!(Some(65), Some(66), Some(0)));
+    }
+
+    #[test]
+    fn test_comment_in_string() {
+        let mut keyboard: Keyboard = r#"0..1 "//" // slashes"#.parse().unwrap();
+
+        assert_eq!(keys(&mut keyboard, &[0, 1, 2, 3]), vec!(Some(47), Some(0), Some(47), Some(0)));
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let e = "0..10 65\n10..5 66\n".parse::<Keyboard>().unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::InvalidKeyboardScript));
+        assert_eq!((e.line, e.text.as_str()), (2, "10..5"));
+
+        let e = format!("0..{} \"ab\"\n", u64::MAX / 2).parse::<Keyboard>().unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::InvalidKeyboardScript));
+
+        let e = "0..10 SHIFT\n".parse::<Keyboard>().unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::UnrecognisedKey));
+        assert_eq!(e.text, "SHIFT");
+    }
+}
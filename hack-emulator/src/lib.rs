@@ -0,0 +1,21 @@
+//! Emulator: Library for running .hack binaries on an emulated Hack computer.
+
+mod debugger;
+mod error;
+pub mod gdb;
+mod history;
+mod keyboard;
+mod machine;
+mod screen;
+mod script;
+mod symbols;
+mod trace;
+
+pub use debugger::Debugger;
+pub use error::{EmulatorError, ErrorKind};
+pub use keyboard::{key_code, Keyboard, FRAME_CYCLES};
+pub use machine::{alu, parse_hack, Machine, Status, KBD, RAM_SIZE, ROM_SIZE, SCREEN};
+pub use screen::{ImageFormat, HEIGHT, WIDTH};
+pub use script::run_script;
+pub use symbols::{load, Symbols};
+pub use trace::{Record, Trace, TraceFormat, NO_WRITE};
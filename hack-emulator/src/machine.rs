@@ -0,0 +1,345 @@
+//! Machine: The Hack computer, a CPU with instruction and data memories.
+//!
+//! Each cycle executes one instruction, as the CPU chip would on one clock:
+//! the ALU reads A, D and RAM[A], then M, A, D and PC are written together,
+//! so `AM=M+1` increments the RAM word at the old A.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{EmulatorError, ErrorKind};
+use crate::keyboard::Keyboard;
+use crate::screen::{self, ImageFormat};
+
+/// Words of ROM and of RAM, each addressed by 15 bits.
+pub const ROM_SIZE: usize = 0x8000;
+pub const RAM_SIZE: usize = 0x8000;
+
+/// Bases of the SCREEN and KBD memory maps.
+pub const SCREEN: u16 = 0x4000;
+pub const KBD: u16 = 0x6000;
+
+const ADDRESS_MASK: u16 = 0x7fff;
+
+/// Why a run stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The program is stuck in a loop that changes nothing, such as
+    /// `(END) @END 0;JMP` or `Sys.halt`.
+    Halted,
+    /// The maximum number of cycles ran.
+    MaxCycles,
+    /// The exit condition was met.
+    Until,
+}
+
+pub struct Machine {
+    pub rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    /// Instructions executed since the last reset.
+    pub cycles: u64,
+    /// Key presses written to RAM[KBD] as the cycles they are due at run.
+    pub keyboard: Keyboard,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine {
+            rom: vec![0; ROM_SIZE],
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            keyboard: Keyboard::new(),
+        }
+    }
+
+    /// Load `words` into ROM from address 0, clearing the rest, and reset.
+    pub fn load(&mut self, words: &[u16]) -> Result<(), EmulatorError> {
+        if words.len() > ROM_SIZE {
+            return Err(EmulatorError::new(ErrorKind::ProgramTooLarge, 0, ""))
+        }
+
+        self.rom[..words.len()].copy_from_slice(words);
+        self.rom[words.len()..].iter_mut().for_each(|w| *w = 0);
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Load a `.hack` file of lines of 16 '0'/'1' characters.
+    pub fn load_hack(&mut self, src_hack: &Path) -> Result<(), EmulatorError> {
+        let words = fs::read_to_string(src_hack).map_err(EmulatorError::io)
+            .and_then(|hack| parse_hack(&hack))
+            .map_err(|e| e.in_file(&src_hack.to_string_lossy()))?;
+
+        self.load(&words)
+    }
+
+    /// Assemble and load a `.asm` file, as the CPUEmulator does.
+    pub fn load_asm(&mut self, src_asm: &Path) -> Result<(), EmulatorError> {
+        let (words, _) = assembler::assemble_file(src_asm)?;
+
+        self.load(&words)
+    }
+
+    /// Set PC to 0, as the CPU's reset input does. Registers and RAM keep
+    /// their values.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.cycles = 0;
+        self.keyboard.rewind();
+    }
+
+    /// Load a keyboard script, replacing any keys yet to be pressed.
+    pub fn load_keyboard(&mut self, src_keys: &Path) -> Result<(), EmulatorError> {
+        self.keyboard = fs::read_to_string(src_keys).map_err(EmulatorError::io)
+            .and_then(|script| script.parse())
+            .map_err(|e| e.in_file(&src_keys.to_string_lossy()))?;
+
+        Ok(())
+    }
+
+    /// RAM[A], the M register.
+    pub fn m(&self) -> u16 {
+        self.ram[(self.a & ADDRESS_MASK) as usize]
+    }
+
+    /// The RAM address the instruction at PC writes, if it stores to M.
+    pub fn writes(&self) -> Option<u16> {
+        let word = self.rom[(self.pc & ADDRESS_MASK) as usize];
+
+        if word & 0x8000 != 0 && word & 0b001000 != 0 { Some(self.a & ADDRESS_MASK) } else { None }
+    }
+
+    /// The SCREEN memory map, encoded as an image file.
+    pub fn screen(&self, format: ImageFormat) -> Vec<u8> {
+        screen::encode(&self.ram, format)
+    }
+
+    /// Write the SCREEN memory map to the image file `dst`.
+    pub fn dump_screen(&self, dst: &Path, format: ImageFormat) -> Result<(), EmulatorError> {
+        fs::write(dst, self.screen(format)).map_err(|e| EmulatorError::io(e).in_file(&dst.to_string_lossy()))
+    }
+
+    /// Execute the instruction at PC.
+    pub fn step(&mut self) {
+        if let Some(key) = self.keyboard.update(self.cycles) {
+            self.ram[KBD as usize] = key;
+        }
+
+        let word = self.rom[(self.pc & ADDRESS_MASK) as usize];
+        let a = self.a;
+
+        self.cycles += 1;
+
+        // A-instruction
+        if word & 0x8000 == 0 {
+            self.a = word;
+            self.pc = (self.pc + 1) & ADDRESS_MASK;
+            return
+        }
+
+        // C-instruction, 111a cccc ccdd djjj. Bits 13 and 14 are ignored
+        let y = if word & 0x1000 != 0 { self.m() } else { a };
+        let out = alu(self.d, y, word >> 6 & 0b111111);
+
+        if word & 0b001000 != 0 {
+            self.ram[(a & ADDRESS_MASK) as usize] = out;
+        }
+
+        if word & 0b100000 != 0 {
+            self.a = out;
+        }
+
+        if word & 0b010000 != 0 {
+            self.d = out;
+        }
+
+        self.pc = if jumps(out, word & 0b111) { a & ADDRESS_MASK } else { (self.pc + 1) & ADDRESS_MASK };
+    }
+
+    /// Execute up to `max_cycles` instructions, stopping early if the
+    /// program halts.
+    pub fn run(&mut self, max_cycles: u64) -> Status {
+        self.run_until(max_cycles, |_| false)
+    }
+
+    /// Execute up to `max_cycles` instructions, stopping early if the
+    /// program halts or `until` is true before an instruction.
+    pub fn run_until(&mut self, max_cycles: u64, until: impl Fn(&Machine) -> bool) -> Status {
+        for _ in 0..max_cycles {
+            if until(self) {
+                return Status::Until
+            }
+
+            if self.halted() {
+                return Status::Halted
+            }
+
+            self.step();
+        }
+
+        if until(self) { Status::Until } else { Status::MaxCycles }
+    }
+
+    /// Is the instruction at PC a jump that writes nothing and returns to
+    /// itself, directly or via an `@` loading its own address? Keys yet to
+    /// be pressed could still change the program's path, so it has not
+    /// halted until they are.
+    pub fn halted(&self) -> bool {
+        let pc = self.pc & ADDRESS_MASK;
+        let word = self.rom[pc as usize];
+
+        if word & 0x8000 == 0 || word & 0b111000 != 0 || !self.keyboard.is_done() {
+            return false
+        }
+
+        let y = if word & 0x1000 != 0 { self.m() } else { self.a };
+        let target = self.a & ADDRESS_MASK;
+
+        jumps(alu(self.d, y, word >> 6 & 0b111111), word & 0b111)
+            && (target == pc || target + 1 == pc && self.rom[target as usize] == target)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Halted => write!(f, "halted"),
+            Status::MaxCycles => write!(f, "max cycles"),
+            Status::Until => write!(f, "until"),
+        }
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Machine::new()
+    }
+}
+
+/// Hack ALU output for inputs `x` and `y` and control bits
+/// `zx nx zy ny f no`, the six c-bits of a C-instruction.
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & 1 << (5 - n) != 0;
+
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+
+    if bit(5) { !out } else { out }
+}
+
+// Jump bits j1 j2 j3 select out < 0, out = 0 and out > 0
+fn jumps(out: u16, jump: u16) -> bool {
+    let negative = out & 0x8000 != 0;
+    let zero = out == 0;
+
+    (jump & 0b100 != 0 && negative) || (jump & 0b010 != 0 && zero)
+        || (jump & 0b001 != 0 && !negative && !zero)
+}
+
+/// Machine words from `.hack` lines of 16 '0'/'1' characters. Blank lines
+/// are skipped.
+pub fn parse_hack(hack: &str) -> Result<Vec<u16>, EmulatorError> {
+    let mut words = Vec::new();
+
+    for (i, line) in hack.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue
+        }
+
+        match u16::from_str_radix(line, 2) {
+            Ok(word) if line.len() == 16 => words.push(word),
+            _ => return Err(EmulatorError::new(ErrorKind::UnrecognisedWord, i + 1, line)),
+        }
+    }
+
+    Ok(words)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: u16 = 17;
+    const Y: u16 = 3;
+
+    #[test]
+    fn test_alu_computes_every_comp() {
+        let table = [
+            (0b101010, 0), (0b111111, 1), (0b111010, 0xffff),
+            (0b001100, X), (0b110000, Y), (0b001101, !X), (0b110001, !Y),
+            (0b001111, X.wrapping_neg()), (0b110011, Y.wrapping_neg()),
+            (0b011111, X + 1), (0b110111, Y + 1), (0b001110, X - 1), (0b110010, Y - 1),
+            (0b000010, X + Y), (0b010011, X - Y), (0b000111, Y.wrapping_sub(X)),
+            (0b000000, X & Y), (0b010101, X | Y),
+        ];
+
+        for (control, expected) in table.iter() {
+            assert_eq!(alu(X, Y, *control), *expected, "control {:06b}", control);
+        }
+    }
+
+    #[test]
+    fn test_jumps() {
+        let outs = [0xffff, 0, 1];
+        let table = [
+            (0b000, [false, false, false]), (0b001, [false, false, true]),
+            (0b010, [false, true, false]), (0b011, [false, true, true]),
+            (0b100, [true, false, false]), (0b101, [true, false, true]),
+            (0b110, [true, true, false]), (0b111, [true, true, true]),
+        ];
+
+        for (jump, expected) in table.iter() {
+            for (out, expected) in outs.iter().zip(expected.iter()) {
+                assert_eq!(jumps(*out, *jump), *expected, "jump {:03b} out {}", jump, out);
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_writes_m_at_old_a() {
+        let mut machine = Machine::new();
+        machine.load(&[5, 0xfde8]).unwrap(); // @5, AM=M+1
+        machine.ram[5] = 41;
+
+        machine.run(2);
+
+        assert_eq!((machine.ram[5], machine.a, machine.pc), (42, 42, 2));
+    }
+
+    #[test]
+    fn test_step_jumps_to_old_a() {
+        let mut machine = Machine::new();
+        machine.load(&[7, 0xeea7]).unwrap(); // @7, A=-1;JMP
+
+        machine.run(2);
+
+        assert_eq!((machine.a, machine.pc), (0xffff, 7));
+    }
+
+    #[test]
+    fn test_parse_hack() {
+        assert_eq!(parse_hack("0000000000000101\n\n1110110000010000\n").unwrap(), vec!(5, 0xec10));
+    }
+
+    #[test]
+    fn test_parse_hack_error_line() {
+        let e = parse_hack("0000000000000101\n012\n").unwrap_err();
+
+        assert!(matches!(e.kind, ErrorKind::UnrecognisedWord));
+        assert_eq!((e.line, e.text.as_str()), (2, "012"));
+    }
+}
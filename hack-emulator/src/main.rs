@@ -0,0 +1,271 @@
+//! Executable for running .hack binaries headless.
+//!
+//! Usage: hack-emulator [options] <file.hack>
+//!        hack-emulator <file.tst>
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use emulator::{ImageFormat, Machine, Status, Symbols, Trace, TraceFormat, RAM_SIZE, ROM_SIZE};
+
+const USAGE: &str = "\
+Usage: hack-emulator [options] <file.hack>
+       hack-emulator <file.tst>
+
+Runs the program until it halts in a tight loop such as (END) @END 0;JMP,
+then prints the registers and RAM[0..16]. Or runs a CPUEmulator test script,
+comparing its output with its .cmp file.
+
+Options:
+    --max-cycles N       Stop after N instructions (default 1000000)
+    --until-label NAME   Stop when PC reaches a label from <file.sym>
+    --until-ram ADDR=VALUE
+                         Stop when RAM[ADDR] holds VALUE
+    --keys=FILE          Press keys from a keyboard script as the program runs
+    --screen=FILE        Write the screen to FILE at the end, as .pbm or .png
+    --screen-every=N     Also write the screen every N cycles, to FILE-<cycle>
+    --trace=FILE         Write a record of each instruction executed to FILE
+    --trace-format=FORMAT
+                         text (default), or binary
+    --trace-pc=START..END
+                         Only trace instructions at PCs START..END
+    --trace-label=NAME   Only trace instructions from a label from <file.sym>
+                         to the next, or in a function
+
+Exits with status 2 if stopped by --max-cycles.";
+
+const MAX_CYCLES: u64 = 1_000_000;
+
+enum Until {
+    Label(String),
+    Ram(u16, u16),
+}
+
+enum TraceOnly {
+    Pcs(u16, u16),
+    Label(String),
+}
+
+fn main() {
+    let mut max_cycles = MAX_CYCLES;
+    let mut until = Vec::new();
+    let mut screen = None;
+    let mut every = None;
+    let mut keys = None;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_only = Vec::new();
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-cycles" => max_cycles = number(&args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--max-cycles=") => max_cycles = number(&arg["--max-cycles=".len()..]),
+            "--until-label" => until.push(Until::Label(args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with("--until-label=") => until.push(Until::Label(arg["--until-label=".len()..].to_string())),
+            "--until-ram" => until.push(until_ram(&args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with("--until-ram=") => until.push(until_ram(&arg["--until-ram=".len()..])),
+            _ if arg.starts_with("--keys=") => keys = Some(PathBuf::from(&arg["--keys=".len()..])),
+            _ if arg.starts_with("--screen=") => screen = Some(PathBuf::from(&arg["--screen=".len()..])),
+            _ if arg.starts_with("--screen-every=") => every = Some(number(&arg["--screen-every=".len()..])),
+            _ if arg.starts_with("--trace=") => trace = Some(PathBuf::from(&arg["--trace=".len()..])),
+            _ if arg.starts_with("--trace-format=") => trace_format = arg["--trace-format=".len()..].parse().unwrap_or_else(|_| usage()),
+            _ if arg.starts_with("--trace-pc=") => {
+                let (start, end) = pc_range(&arg["--trace-pc=".len()..]);
+                trace_only.push(TraceOnly::Pcs(start, end));
+            },
+            _ if arg.starts_with("--trace-label=") => trace_only.push(TraceOnly::Label(arg["--trace-label=".len()..].to_string())),
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+
+    let path = PathBuf::from(path.unwrap_or_else(|| usage()));
+
+    if path.extension().and_then(|e| e.to_str()) == Some("tst") {
+        match emulator::run_script(&path) {
+            Ok(()) => println!("End of script - Comparison ended successfully"),
+            Err(e) => exit(e),
+        }
+
+        return
+    }
+    let screen = screen.map(|s| {
+        let format = image_format(&s);
+        (s, format)
+    });
+
+    if every.is_some() && screen.is_none() || every == Some(0) || !trace_only.is_empty() && trace.is_none() {
+        usage();
+    }
+
+    let mut machine = Machine::new();
+
+    if let Err(e) = machine.load_hack(&path) {
+        exit(e);
+    }
+
+    if let Some(Err(e)) = keys.map(|k| machine.load_keyboard(&k)) {
+        exit(e);
+    }
+
+    let mut trace = trace.map(|t| {
+        let file = File::create(&t).unwrap_or_else(|e| exit(format!("{}: {}", t.display(), e)));
+        let mut trace = Trace::new(BufWriter::new(file), trace_format);
+
+        for (start, end) in trace_ranges(&path, &trace_only) {
+            trace.include(start, end);
+        }
+
+        (t, trace)
+    });
+
+    // Stop before the instruction at a label, or once RAM holds a value
+    let pcs = labels(&path, &until);
+    let rams: Vec<(u16, u16)> = until.iter().filter_map(|u| match u {
+        Until::Ram(address, value) => Some((*address, *value)),
+        Until::Label(_) => None,
+    }).collect();
+    let until = |m: &Machine| {
+        pcs.contains(&m.pc) || rams.iter().any(|(address, value)| m.ram[*address as usize] == *value)
+    };
+
+    let status = loop {
+        let remaining = max_cycles - machine.cycles;
+        let cycles = every.map_or(remaining, |n| n.min(remaining));
+        let status = match &mut trace {
+            Some((t, trace)) => trace.run_until(&mut machine, cycles, until)
+                .unwrap_or_else(|e| exit(format!("{}: {}", t.display(), e))),
+            None => machine.run_until(cycles, until),
+        };
+
+        if status != Status::MaxCycles || machine.cycles >= max_cycles {
+            break status
+        }
+
+        if let Some((dst, format)) = &screen {
+            dump_screen(&machine, &numbered(dst, machine.cycles), *format);
+        }
+    };
+
+    if let Some((dst, format)) = &screen {
+        dump_screen(&machine, dst, *format);
+    }
+
+    if let Some((t, mut trace)) = trace {
+        trace.flush().unwrap_or_else(|e| exit(format!("{}: {}", t.display(), e)));
+    }
+
+    println!("{}  cycles {}  PC {}  A {}  D {}", status, machine.cycles, machine.pc, machine.a, machine.d as i16);
+
+    for (address, value) in machine.ram[..16].iter().enumerate() {
+        println!("RAM[{}] {}", address, *value as i16);
+    }
+
+    if status == Status::MaxCycles {
+        std::process::exit(2);
+    }
+}
+
+// Addresses of the --until-label labels, from the symbol file beside the program
+fn labels(path: &Path, until: &[Until]) -> Vec<u16> {
+    let names: Vec<&str> = until.iter().filter_map(|u| match u {
+        Until::Label(name) => Some(name.as_str()),
+        Until::Ram(..) => None,
+    }).collect();
+
+    if names.is_empty() {
+        return Vec::new()
+    }
+
+    let path_sym = path.with_extension("sym");
+    let labels = fs::read_to_string(&path_sym).map_err(|e| e.to_string())
+        .and_then(|sym| assembler::parse_labels(&sym).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| exit(format!("{}: {}", path_sym.display(), e)));
+
+    names.iter().map(|name| {
+        match labels.iter().find(|(label, _)| label == name) {
+            Some((_, address)) => *address,
+            None => exit(format!("{}: error: Undefined label '{}'", path_sym.display(), name)),
+        }
+    }).collect()
+}
+
+// PCs to trace, from --trace-pc ranges and the extents of --trace-label labels
+fn trace_ranges(path: &Path, trace_only: &[TraceOnly]) -> Vec<(u16, u16)> {
+    let mut symbols = None;
+
+    trace_only.iter().map(|t| match t {
+        TraceOnly::Pcs(start, end) => (*start, *end),
+        TraceOnly::Label(name) => {
+            let path_sym = path.with_extension("sym");
+            let symbols = symbols.get_or_insert_with(|| {
+                fs::read_to_string(&path_sym).map_err(|e| e.to_string())
+                    .and_then(|sym| Symbols::from_sym(&sym).map_err(|e| e.to_string()))
+                    .unwrap_or_else(|e| exit(format!("{}: {}", path_sym.display(), e)))
+            });
+
+            symbols.extent(name)
+                .unwrap_or_else(|| exit(format!("{}: error: Undefined label '{}'", path_sym.display(), name)))
+        },
+    }).collect()
+}
+
+// START..END
+fn pc_range(range: &str) -> (u16, u16) {
+    range.split_once("..")
+        .and_then(|(start, end)| Some((start.parse::<u16>().ok()?, end.parse::<u16>().ok()?)))
+        .filter(|(start, end)| start < end && *end as usize <= ROM_SIZE)
+        .unwrap_or_else(|| usage())
+}
+
+// ADDR=VALUE, where VALUE may be negative
+fn until_ram(condition: &str) -> Until {
+    let (address, value) = condition.split_once('=').unwrap_or_else(|| usage());
+    let address = address.parse().ok().filter(|a| (*a as usize) < RAM_SIZE).unwrap_or_else(|| usage());
+    let value = match value.parse::<i16>() {
+        Ok(value) => value as u16,
+        Err(_) => value.parse().unwrap_or_else(|_| usage()),
+    };
+
+    Until::Ram(address, value)
+}
+
+fn number(text: &str) -> u64 {
+    text.parse().unwrap_or_else(|_| usage())
+}
+
+fn image_format(path: &Path) -> ImageFormat {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_else(|| usage());
+
+    extension.parse().unwrap_or_else(|_| usage())
+}
+
+// Screen.png becomes Screen-00001000.png at cycle 1000
+fn numbered(path: &Path, cycles: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{}-{:08}.{}", stem, cycles, extension))
+}
+
+fn dump_screen(machine: &Machine, dst: &Path, format: ImageFormat) {
+    if let Err(e) = machine.dump_screen(dst, format) {
+        exit(e);
+    }
+}
+
+fn exit(e: impl fmt::Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1,212 @@
+//! Screen: Encode the SCREEN memory map as a monochrome image.
+//!
+//! The screen is 512x256 pixels, 32 words per row from RAM[SCREEN]. Bit 0
+//! of each word is its leftmost pixel, and a set bit is black.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::machine::SCREEN;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+const ROW_BYTES: usize = WIDTH / 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Binary portable bitmap, P4.
+    #[default]
+    Pbm,
+    /// 1-bit greyscale PNG, uncompressed.
+    Png,
+}
+
+impl ImageFormat {
+    /// Conventional file extension for the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseImageFormatError;
+
+impl FromStr for ImageFormat {
+    type Err = ParseImageFormatError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pbm" => Ok(ImageFormat::Pbm),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(ParseImageFormatError),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Encode the screen in `ram` as an image file.
+pub fn encode(ram: &[u16], format: ImageFormat) -> Vec<u8> {
+    let rows = rows(ram);
+
+    match format {
+        ImageFormat::Pbm => pbm(&rows),
+        ImageFormat::Png => png(&rows),
+    }
+}
+
+// Rows of packed pixels, leftmost in the most significant bit, 1 is black
+fn rows(ram: &[u16]) -> Vec<[u8; ROW_BYTES]> {
+    let screen = &ram[SCREEN as usize..SCREEN as usize + HEIGHT * WIDTH / 16];
+
+    screen.chunks(WIDTH / 16).map(|words| {
+        let mut row = [0; ROW_BYTES];
+
+        for (i, word) in words.iter().enumerate() {
+            let bits = word.reverse_bits();
+            row[2 * i] = (bits >> 8) as u8;
+            row[2 * i + 1] = bits as u8;
+        }
+
+        row
+    }).collect()
+}
+
+fn pbm(rows: &[[u8; ROW_BYTES]]) -> Vec<u8> {
+    let mut image = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+
+    for row in rows {
+        image.extend_from_slice(row);
+    }
+
+    image
+}
+
+fn png(rows: &[[u8; ROW_BYTES]]) -> Vec<u8> {
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    // Width, height, bit depth 1, greyscale, default compression, filter
+    // and no interlace
+    let mut header = Vec::new();
+    header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+    chunk(&mut image, b"IHDR", &header);
+
+    // Each row has filter type 0, and greyscale 0 is black
+    let mut pixels = Vec::with_capacity(rows.len() * (ROW_BYTES + 1));
+
+    for row in rows {
+        pixels.push(0);
+        pixels.extend(row.iter().map(|b| !b));
+    }
+
+    chunk(&mut image, b"IDAT", &zlib(&pixels));
+    chunk(&mut image, b"IEND", &[]);
+
+    image
+}
+
+fn chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+
+    let crc = crc32(kind.iter().chain(data));
+    image.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream of stored (uncompressed) deflate blocks
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let last = (i + 1 == blocks.len()) as u8;
+        let length = block.len() as u16;
+
+        stream.push(last);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::RAM_SIZE;
+
+    #[test]
+    fn test_rows_put_bit_0_leftmost() {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN as usize] = 0x0001;
+        ram[SCREEN as usize + 33] = 0x8000;
+
+        let rows = rows(&ram);
+
+        assert_eq!((rows[0][0], rows[1][3]), (0x80, 0x01));
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN as usize] = 0x00ff;
+
+        let image = encode(&ram, ImageFormat::Pbm);
+
+        assert!(image.starts_with(b"P4\n512 256\n\xff\x00"));
+        assert_eq!(image.len(), 11 + HEIGHT * ROW_BYTES);
+    }
+
+    #[test]
+    fn test_png_chunks() {
+        let image = encode(&vec![0; RAM_SIZE], ImageFormat::Png);
+
+        assert!(image.starts_with(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+        assert!(image.ends_with(b"\x00\x00\x00\x00IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789".iter()), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
@@ -0,0 +1,516 @@
+//! Script: Run nand2tetris CPUEmulator test scripts.
+//!
+//! A `.tst` script loads a program, sets registers and RAM, runs clock
+//! cycles and outputs variables to a `.out` file, which is compared line by
+//! line with a `.cmp` file:
+//!
+//! ```text
+//! load Mult.asm,
+//! output-file Mult.out,
+//! compare-to Mult.cmp,
+//! output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+//!
+//! set RAM[0] 2, set RAM[1] 3;
+//! repeat 20 {
+//!     ticktock;
+//! }
+//! output;
+//! ```
+//!
+//! Each `tock` (or `ticktock`) executes one instruction, and `tick` does
+//! nothing. `echo` and `clear-echo` are accepted, but not shown. `repeat`
+//! needs a count, as there is no cycle limit to end a repeat forever. Files
+//! are relative to the script's directory.
+
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::vec;
+
+use crate::error::{EmulatorError, ErrorKind};
+use crate::machine::{Machine, RAM_SIZE, ROM_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variable {
+    A,
+    D,
+    PC,
+    Time,
+    Ram(u16),
+    Rom(u16),
+}
+
+/// An `output-list` entry, e.g. `RAM[0]%D2.6.2` is a decimal right aligned
+/// in 6 characters, with 2 spaces either side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Column {
+    name: String,
+    variable: Variable,
+    format: char,
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Condition {
+    variable: Variable,
+    op: String,
+    value: u16,
+}
+
+type Block = Vec<(usize, Command)>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    Repeat(u64, Block),
+    While(Condition, Block),
+    Tick,
+    Tock,
+    Output,
+    Echo,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Punct(char),
+}
+
+/// Run the script `src_tst`, writing its output file. Fails at the first
+/// output line that differs from the compare file.
+pub fn run_script(src_tst: &Path) -> Result<(), EmulatorError> {
+    let file = src_tst.to_string_lossy();
+    let script = fs::read_to_string(src_tst).map_err(|e| EmulatorError::io(e).in_file(&file))?;
+    let commands = parse(&script).map_err(|e| e.in_file(&file))?;
+
+    let mut tester = Tester {
+        machine: Machine::new(),
+        dir: src_tst.parent().map(Path::to_path_buf).unwrap_or_default(),
+        out: None,
+        lines: Vec::new(),
+        cmp: None,
+        columns: Vec::new(),
+    };
+
+    let result = tester.execute(&commands).map_err(|e| e.in_file(&file));
+
+    // Keep the output so far, to see where it went wrong
+    if let Some(dst) = &tester.out {
+        let mut out = tester.lines.join("\n");
+        out.push('\n');
+
+        fs::write(dst, out).map_err(|e| EmulatorError::io(e).in_file(&dst.to_string_lossy()))?;
+    }
+
+    result
+}
+
+struct Tester {
+    machine: Machine,
+    dir: PathBuf,
+    out: Option<PathBuf>,
+    lines: Vec<String>,
+    cmp: Option<Vec<String>>,
+    columns: Vec<Column>,
+}
+
+impl Tester {
+    fn execute(&mut self, commands: &[(usize, Command)]) -> Result<(), EmulatorError> {
+        for (line, command) in commands {
+            match command {
+                Command::Load(file) => {
+                    let path = self.dir.join(file);
+
+                    match path.extension().and_then(|e| e.to_str()) {
+                        Some("asm") => self.machine.load_asm(&path)?,
+                        _ => self.machine.load_hack(&path)?,
+                    }
+                },
+                Command::OutputFile(file) => self.out = Some(self.dir.join(file)),
+                Command::CompareTo(file) => {
+                    let path = self.dir.join(file);
+                    let cmp = fs::read_to_string(&path)
+                        .map_err(|e| EmulatorError::io(e).in_file(&path.to_string_lossy()))?;
+
+                    self.cmp = Some(cmp.lines().map(String::from).collect());
+                },
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    self.output(header(columns))?;
+                },
+                Command::Set(variable, value) => self.set(*variable, *value),
+                Command::Repeat(n, block) => {
+                    for _ in 0..*n {
+                        self.execute(block)?;
+                    }
+                },
+                Command::While(condition, block) => {
+                    while self.holds(condition) {
+                        self.execute(block)?;
+                    }
+                },
+                Command::Tick | Command::Echo => (),
+                Command::Tock => self.machine.step(),
+                Command::Output => {
+                    if self.columns.is_empty() {
+                        return Err(EmulatorError::new(ErrorKind::InvalidScript, *line, "output"))
+                    }
+
+                    let line = row(&self.columns, |v| self.get(v), self.machine.cycles);
+                    self.output(line)?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn output(&mut self, line: String) -> Result<(), EmulatorError> {
+        self.lines.push(line);
+
+        let n = self.lines.len();
+        let line = &self.lines[n - 1];
+
+        match self.cmp.as_ref().map(|cmp| cmp.get(n - 1).map_or("", |l| l.as_str())) {
+            Some(expected) if expected.trim_end() != line.trim_end() => {
+                let out = self.out.as_ref().map(|p| p.to_string_lossy().into_owned());
+                let mut e = EmulatorError::new(ErrorKind::ComparisonFailure(expected.to_string()), n, line);
+                e.file = out;
+
+                Err(e)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn get(&self, variable: Variable) -> u16 {
+        match variable {
+            Variable::A => self.machine.a,
+            Variable::D => self.machine.d,
+            Variable::PC => self.machine.pc,
+            Variable::Time => self.machine.cycles as u16,
+            Variable::Ram(address) => self.machine.ram[address as usize],
+            Variable::Rom(address) => self.machine.rom[address as usize],
+        }
+    }
+
+    fn set(&mut self, variable: Variable, value: u16) {
+        match variable {
+            Variable::A => self.machine.a = value,
+            Variable::D => self.machine.d = value,
+            Variable::PC => self.machine.pc = value,
+            Variable::Time => self.machine.cycles = value as u64,
+            Variable::Ram(address) => self.machine.ram[address as usize] = value,
+            Variable::Rom(address) => self.machine.rom[address as usize] = value,
+        }
+    }
+
+    // Signed comparison, as values are shown
+    fn holds(&self, condition: &Condition) -> bool {
+        let x = self.get(condition.variable) as i16;
+        let y = condition.value as i16;
+
+        match condition.op.as_str() {
+            "=" => x == y,
+            "<>" => x != y,
+            "<" => x < y,
+            ">" => x > y,
+            "<=" => x <= y,
+            _ => x >= y,
+        }
+    }
+}
+
+// Column names centred in each column's full width
+fn header(columns: &[Column]) -> String {
+    let mut line = String::from("|");
+
+    for column in columns {
+        let total = column.left + column.width + column.right;
+        let name: String = column.name.chars().take(total).collect();
+        let left = (total - name.len()) / 2;
+
+        line.push_str(&format!("{}{}{}|", " ".repeat(left), name, " ".repeat(total - left - name.len())));
+    }
+
+    line
+}
+
+fn row(columns: &[Column], get: impl Fn(Variable) -> u16, cycles: u64) -> String {
+    let mut line = String::from("|");
+
+    for column in columns {
+        let value = get(column.variable);
+        let text = match (column.variable, column.format) {
+            (Variable::Time, _) => cycles.to_string(),
+            (_, 'X') => format!("{:04X}", value),
+            (_, 'B') => format!("{:016b}", value),
+            _ => (value as i16).to_string(),
+        };
+
+        // Keep the low digits of values too wide for the column
+        let text = &text[text.len().saturating_sub(column.width)..];
+        let width = column.width;
+        let text = if column.format == 'S' { format!("{:<width$}", text) } else { format!("{:>width$}", text) };
+
+        line.push_str(&format!("{}{}{}|", " ".repeat(column.left), text, " ".repeat(column.right)));
+    }
+
+    line
+}
+
+fn parse(script: &str) -> Result<Block, EmulatorError> {
+    parse_block(&mut tokenize(script)?.into_iter().peekable(), None)
+}
+
+// Commands up to the end of the script, or the `}` closing a block opened
+// at line `open`
+fn parse_block(tokens: &mut Peekable<vec::IntoIter<(usize, Token)>>, open: Option<usize>) -> Result<Block, EmulatorError> {
+    let mut commands = Vec::new();
+
+    loop {
+        let (line, token) = match tokens.next() {
+            Some(token) => token,
+            None => match open {
+                Some(line) => return Err(EmulatorError::new(ErrorKind::InvalidScript, line, "{")),
+                None => return Ok(commands),
+            },
+        };
+
+        let error = |text: &str| EmulatorError::new(ErrorKind::InvalidScript, line, text);
+
+        let name = match token {
+            Token::Punct('}') if open.is_some() => return Ok(commands),
+            Token::Punct(',') | Token::Punct(';') | Token::Punct('!') => continue,
+            Token::Word(name) => name,
+            Token::Text(text) => return Err(error(&text)),
+            Token::Punct(c) => return Err(error(&c.to_string())),
+        };
+
+        let mut args = Vec::new();
+
+        while let Some((_, Token::Word(arg))) | Some((_, Token::Text(arg))) = tokens.peek() {
+            args.push(arg.clone());
+            tokens.next();
+        }
+
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        let command = match (name.as_str(), args.as_slice()) {
+            ("load", [file]) => Command::Load(file.to_string()),
+            ("output-file", [file]) => Command::OutputFile(file.to_string()),
+            ("compare-to", [file]) => Command::CompareTo(file.to_string()),
+            ("output-list", columns) if !columns.is_empty() => {
+                let columns: Option<Vec<Column>> = columns.iter().map(|c| column(c)).collect();
+                Command::OutputList(columns.ok_or_else(|| error(&args.join(" ")))?)
+            },
+            ("set", [variable, v]) => {
+                Command::Set(self::variable(variable).ok_or_else(|| error(variable))?, value(v).ok_or_else(|| error(v))?)
+            },
+            ("repeat", []) => return Err(EmulatorError::new(ErrorKind::UnboundedRepeat, line, "repeat")),
+            ("repeat", [n]) => {
+                let n = n.parse().map_err(|_| error(n))?;
+                Command::Repeat(n, parse_body(tokens, line)?)
+            },
+            ("while", [variable, op, v]) => {
+                let condition = Condition {
+                    variable: self::variable(variable).ok_or_else(|| error(variable))?,
+                    op: ["=", "<>", "<", ">", "<=", ">="].iter().find(|o| *o == op).ok_or_else(|| error(op))?.to_string(),
+                    value: value(v).ok_or_else(|| error(v))?,
+                };
+                Command::While(condition, parse_body(tokens, line)?)
+            },
+            ("tick", []) => Command::Tick,
+            ("tock", []) | ("ticktock", []) => Command::Tock,
+            ("output", []) => Command::Output,
+            ("echo", [_]) | ("clear-echo", []) => Command::Echo,
+            _ => return Err(error(&name)),
+        };
+
+        if !matches!(command, Command::Repeat(..) | Command::While(..)) {
+            match tokens.next() {
+                Some((_, Token::Punct(',' | ';' | '!'))) => (),
+                _ => return Err(error(&name)),
+            }
+        }
+
+        commands.push((line, command));
+    }
+}
+
+fn parse_body(tokens: &mut Peekable<vec::IntoIter<(usize, Token)>>, line: usize) -> Result<Block, EmulatorError> {
+    match tokens.next() {
+        Some((open, Token::Punct('{'))) => parse_block(tokens, Some(open)),
+        _ => Err(EmulatorError::new(ErrorKind::InvalidScript, line, "{")),
+    }
+}
+
+// Words, "quoted text" and punctuation, without comments
+fn tokenize(script: &str) -> Result<Vec<(usize, Token)>, EmulatorError> {
+    let mut tokens = Vec::new();
+    let mut chars = script.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            _ if c.is_whitespace() => (),
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                let start = line;
+                let mut previous = chars.next();
+
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == Some('*') => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            previous = Some(c);
+                        },
+                        None => return Err(EmulatorError::new(ErrorKind::InvalidScript, start, "/*")),
+                    }
+                }
+            },
+            '"' => {
+                let text: String = chars.by_ref().take_while(|c| *c != '"').collect();
+                line += text.matches('\n').count();
+                tokens.push((line, Token::Text(text)));
+            },
+            ',' | ';' | '!' | '{' | '}' => tokens.push((line, Token::Punct(c))),
+            _ => {
+                let mut word = c.to_string();
+
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace() && !",;!{}\"".contains(**c)) {
+                    word.push(*c);
+                    chars.next();
+                }
+
+                tokens.push((line, Token::Word(word)));
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+// A, D, PC, time, RAM[n] or ROM[n]
+fn variable(name: &str) -> Option<Variable> {
+    let index = |prefix: &str, size: usize| {
+        name.strip_prefix(prefix)?.strip_suffix(']')?.parse::<u16>().ok().filter(|i| (*i as usize) < size)
+    };
+
+    match name {
+        "A" => Some(Variable::A),
+        "D" => Some(Variable::D),
+        "PC" => Some(Variable::PC),
+        "time" => Some(Variable::Time),
+        _ => index("RAM[", RAM_SIZE).map(Variable::Ram)
+            .or_else(|| index("ROM[", ROM_SIZE).map(Variable::Rom))
+            .or_else(|| index("ROM32K[", ROM_SIZE).map(Variable::Rom)),
+    }
+}
+
+// NAME%FL.W.R, with format B, X, D or S
+fn column(text: &str) -> Option<Column> {
+    let (name, spec) = text.split_once('%')?;
+    let mut chars = spec.chars();
+    let format = chars.next().filter(|f| "BXDS".contains(*f))?;
+    let sizes: Vec<usize> = chars.as_str().split('.').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+
+    match sizes.as_slice() {
+        [left, width, right] => Some(Column {
+            name: name.to_string(),
+            variable: variable(name)?,
+            format,
+            left: *left,
+            width: *width,
+            right: *right,
+        }),
+        _ => None,
+    }
+}
+
+// Decimal, possibly negative, or %X, %B or %D prefixed
+fn value(text: &str) -> Option<u16> {
+    let (digits, radix) = match text.get(..2) {
+        Some("%X") => (&text[2..], 16),
+        Some("%B") => (&text[2..], 2),
+        Some("%D") => (&text[2..], 10),
+        _ => (text, 10),
+    };
+
+    match digits.strip_prefix('-') {
+        Some(digits) if radix == 10 => digits.parse::<u16>().ok().filter(|v| *v <= 0x8000).map(|v| v.wrapping_neg()),
+        _ => u16::from_str_radix(digits, radix).ok(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let commands = parse("load Add.hack, // comment\n/* block\n */ set RAM[0] -1;\nrepeat 2 {\n  ticktock;\n}\noutput;").unwrap();
+
+        assert_eq!(commands, vec!(
+            (1, Command::Load(String::from("Add.hack"))),
+            (3, Command::Set(Variable::Ram(0), 0xffff)),
+            (4, Command::Repeat(2, vec!((5, Command::Tock)))),
+            (7, Command::Output),
+        ));
+    }
+
+    #[test]
+    fn test_parse_while() {
+        let commands = parse("while RAM[1] <> 0 { tock; }").unwrap();
+
+        assert!(matches!(&commands[0].1, Command::While(Condition { variable: Variable::Ram(1), op, value: 0 }, _) if op == "<>"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = parse("load A.hack,\nset RAM[99999] 1;\n").unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::InvalidScript));
+        assert_eq!((e.line, e.text.as_str()), (2, "RAM[99999]"));
+
+        let e = parse("repeat 3 {\ntock;\n").unwrap_err();
+        assert_eq!((e.line, e.text.as_str()), (1, "{"));
+
+        let e = parse("repeat {\ntock;\n}\n").unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::UnboundedRepeat));
+        assert_eq!((e.line, e.text.as_str()), (1, "repeat"));
+
+        let e = parse("output").unwrap_err();
+        assert_eq!(e.text, "output");
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(value("%XFFFF"), Some(0xffff));
+        assert_eq!(value("%B101"), Some(5));
+        assert_eq!(value("-32768"), Some(0x8000));
+        assert_eq!(value("65536"), None);
+    }
+
+    #[test]
+    fn test_header_and_row() {
+        let columns = vec!(column("RAM[0]%D2.6.2").unwrap(), column("A%X1.4.1").unwrap(), column("D%B1.8.1").unwrap());
+        let values = |v| match v { Variable::Ram(0) => 0xfffe, Variable::A => 0x4000, _ => 0x1ff };
+
+        assert_eq!(header(&columns), "|  RAM[0]  |  A   |    D     |");
+        assert_eq!(row(&columns, values, 0), "|      -2  | 4000 | 11111111 |");
+    }
+}
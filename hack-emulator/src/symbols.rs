@@ -0,0 +1,167 @@
+//! Symbols: Name ROM and RAM addresses from the assembler's symbol table.
+//!
+//! Programs translated from VM code name each function `Class.function`,
+//! so the nearest label with a '.' before an address is its function.
+
+use std::fs;
+use std::path::Path;
+
+use assembler::symboltable::{self, Kind, SymbolTable};
+
+use crate::error::EmulatorError;
+use crate::machine::{Machine, ROM_SIZE};
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    /// ROM addresses, sorted by address then name.
+    labels: Vec<(String, u16)>,
+    /// RAM addresses of variables, definitions and predefined symbols.
+    variables: Vec<(String, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn from_table(symbol_table: &SymbolTable) -> Self {
+        let mut symbols = Symbols::new();
+
+        for (name, address, kind) in symbol_table.entries() {
+            let name = symboltable::readable(name);
+
+            match kind {
+                Kind::Label => symbols.labels.push((name, address)),
+                _ => symbols.variables.push((name, address)),
+            }
+        }
+
+        symbols.labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        symbols
+    }
+
+    /// Symbols from a `.sym` map written by the assembler, and the
+    /// predefined symbols.
+    pub fn from_sym(sym: &str) -> Result<Self, EmulatorError> {
+        let mut symbol_table = SymbolTable::new();
+
+        for (name, address) in assembler::parse_labels(sym)? {
+            symbol_table.addEntry(&name, address, Kind::Label);
+        }
+
+        for (name, address) in assembler::parse_variables(sym)? {
+            symbol_table.addEntry(&name, address, Kind::Variable);
+        }
+
+        Ok(Symbols::from_table(&symbol_table))
+    }
+
+    /// ROM address of a label.
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(n, _)| n == name).map(|(_, address)| *address)
+    }
+
+    /// RAM address of a variable or predefined symbol, e.g. `SP`.
+    pub fn variable(&self, name: &str) -> Option<u16> {
+        self.variables.iter().find(|(n, _)| n == name).map(|(_, address)| *address)
+    }
+
+    /// The nearest label at or before `address`, and the distance past it.
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        self.nearest(address, |_| true)
+    }
+
+    /// The function `address` is in, and the distance past its label.
+    pub fn function(&self, address: u16) -> Option<(&str, u16)> {
+        self.nearest(address, |name| name.contains('.'))
+    }
+
+    /// ROM addresses from a label up to the next label, or for a function,
+    /// up to the next function.
+    pub fn extent(&self, name: &str) -> Option<(u16, u16)> {
+        let start = self.label(name)?;
+        let end = self.labels.iter()
+            .find(|(n, a)| *a > start && (!name.contains('.') || n.contains('.')))
+            .map_or(ROM_SIZE as u16, |(_, a)| *a);
+
+        Some((start, end))
+    }
+
+    /// `NAME+OFFSET` for the nearest label, or just the address.
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => address.to_string(),
+        }
+    }
+
+    fn nearest(&self, address: u16, filter: impl Fn(&str) -> bool) -> Option<(&str, u16)> {
+        self.labels.iter()
+            .take_while(|(_, a)| *a <= address)
+            .filter(|(name, _)| filter(name))
+            .last()
+            .map(|(name, a)| (name.as_str(), address - a))
+    }
+}
+
+/// Load a program into `machine`: a `.asm` file, assembled with its symbol
+/// table, or a `.hack` file, named by the `.sym` file beside it if any.
+pub fn load(machine: &mut Machine, path: &Path) -> Result<Symbols, EmulatorError> {
+    if path.extension().and_then(|e| e.to_str()) == Some("asm") {
+        let (words, symbol_table) = assembler::assemble_file(path)?;
+        machine.load(&words)?;
+
+        return Ok(Symbols::from_table(&symbol_table))
+    }
+
+    machine.load_hack(path)?;
+
+    let path_sym = path.with_extension("sym");
+
+    match fs::read_to_string(&path_sym) {
+        Ok(sym) => Symbols::from_sym(&sym).map_err(|e| e.in_file(&path_sym.to_string_lossy())),
+        Err(_) => Ok(Symbols::from_table(&SymbolTable::new())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> Symbols {
+        Symbols::from_sym("Sys.init 10 label\nLOOP 14 label\nMain.main 20 label\ncount 16 variable\n").unwrap()
+    }
+
+    #[test]
+    fn test_locate() {
+        let symbols = symbols();
+
+        assert_eq!(symbols.locate(9), None);
+        assert_eq!(symbols.locate(14), Some(("LOOP", 0)));
+        assert_eq!(symbols.describe(17), "LOOP+3");
+    }
+
+    #[test]
+    fn test_function_skips_labels_without_a_dot() {
+        assert_eq!(symbols().function(17), Some(("Sys.init", 7)));
+    }
+
+    #[test]
+    fn test_extent() {
+        let symbols = symbols();
+
+        assert_eq!(symbols.extent("LOOP"), Some((14, 20)));
+        assert_eq!(symbols.extent("Sys.init"), Some((10, 20)));
+        assert_eq!(symbols.extent("Main.main"), Some((20, 0x8000)));
+    }
+
+    #[test]
+    fn test_variables_include_predefined() {
+        let symbols = symbols();
+
+        assert_eq!((symbols.variable("count"), symbols.variable("THAT")), (Some(16), Some(4)));
+        assert_eq!(symbols.label("Main.main"), Some(20));
+    }
+}
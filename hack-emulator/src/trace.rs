@@ -0,0 +1,243 @@
+//! Trace: One record per instruction executed, as text or compact binary.
+//!
+//! A record holds the cycle, the PC and instruction executed, A and D
+//! after it, and the RAM address and value it wrote, if any. As text:
+//!
+//! ```text
+//!       12  PC     5  M=M-1       A     16  D      0  RAM[16] 6
+//! ```
+//!
+//! The binary form is `Record::SIZE` bytes per record, little-endian: the
+//! cycle as a u64, then the PC, instruction, A, D, RAM address and value as
+//! u16s. The address is `NO_WRITE` for instructions that write no RAM.
+//!
+//! Traces of two programs can be diffed, e.g. `cmp` of binary traces gives
+//! the first record, at its offset / `Record::SIZE`, that differs.
+
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+use assembler::Instruction;
+
+use crate::machine::{Machine, Status, ROM_SIZE};
+
+/// Binary records' RAM address for no write.
+pub const NO_WRITE: u16 = 0xffff;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per record.
+    #[default]
+    Text,
+    /// `Record::SIZE` bytes per record.
+    Binary,
+}
+
+#[derive(Debug)]
+pub struct ParseTraceFormatError;
+
+impl FromStr for TraceFormat {
+    type Err = ParseTraceFormatError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(ParseTraceFormatError),
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceFormat::Text => write!(f, "text"),
+            TraceFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub instruction: u16,
+    pub a: u16,
+    pub d: u16,
+    /// RAM address and value written.
+    pub write: Option<(u16, u16)>,
+}
+
+impl Record {
+    pub const SIZE: usize = 20;
+
+    /// Execute the instruction at PC, recording it.
+    pub fn step(machine: &mut Machine) -> Self {
+        let (cycle, pc) = (machine.cycles, machine.pc);
+        let instruction = machine.rom[pc as usize];
+        let address = machine.writes();
+
+        machine.step();
+
+        Record {
+            cycle,
+            pc,
+            instruction,
+            a: machine.a,
+            d: machine.d,
+            write: address.map(|a| (a, machine.ram[a as usize])),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Record::SIZE] {
+        let (address, value) = self.write.unwrap_or((NO_WRITE, 0));
+        let mut bytes = [0; Record::SIZE];
+
+        bytes[..8].copy_from_slice(&self.cycle.to_le_bytes());
+
+        for (i, word) in [self.pc, self.instruction, self.a, self.d, address, value].iter().enumerate() {
+            bytes[8 + 2 * i..10 + 2 * i].copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..Record::SIZE)?;
+        let word = |i: usize| u16::from_le_bytes([bytes[8 + 2 * i], bytes[9 + 2 * i]]);
+        let mut cycle = [0; 8];
+
+        cycle.copy_from_slice(&bytes[..8]);
+
+        Some(Record {
+            cycle: u64::from_le_bytes(cycle),
+            pc: word(0),
+            instruction: word(1),
+            a: word(2),
+            d: word(3),
+            write: Some((word(4), word(5))).filter(|(address, _)| *address != NO_WRITE),
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instruction = match Instruction::decode(self.instruction) {
+            Some(instruction) => instruction.to_string(),
+            None => format!("{:016b}", self.instruction),
+        };
+
+        write!(f, "{:>8}  PC {:>5}  {:<10}  A {:>6}  D {:>6}", self.cycle, self.pc, instruction, self.a, self.d as i16)?;
+
+        if let Some((address, value)) = self.write {
+            write!(f, "  RAM[{}] {}", address, value as i16)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a record of each instruction executed, or only of those at the
+/// PCs included.
+pub struct Trace<W> {
+    output: W,
+    format: TraceFormat,
+    /// Included PCs, if filtered.
+    pcs: Option<Vec<bool>>,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(output: W, format: TraceFormat) -> Self {
+        Trace { output, format, pcs: None }
+    }
+
+    /// Record instructions at PCs `start..end`, as well as any other
+    /// ranges included, rather than all.
+    pub fn include(&mut self, start: u16, end: u16) {
+        let pcs = self.pcs.get_or_insert_with(|| vec![false; ROM_SIZE]);
+        let end = (end as usize).min(ROM_SIZE);
+
+        if let Some(range) = pcs.get_mut(start as usize..end) {
+            range.fill(true);
+        }
+    }
+
+    /// Execute the instruction at PC, recording it if included.
+    pub fn step(&mut self, machine: &mut Machine) -> io::Result<()> {
+        let record = Record::step(machine);
+
+        if self.pcs.as_ref().is_some_and(|pcs| !pcs[record.pc as usize]) {
+            return Ok(())
+        }
+
+        match self.format {
+            TraceFormat::Text => writeln!(self.output, "{}", record),
+            TraceFormat::Binary => self.output.write_all(&record.encode()),
+        }
+    }
+
+    /// `Machine::run_until`, recording each instruction executed.
+    pub fn run_until(&mut self, machine: &mut Machine, max_cycles: u64, until: impl Fn(&Machine) -> bool) -> io::Result<Status> {
+        for _ in 0..max_cycles {
+            if until(machine) {
+                return Ok(Status::Until)
+            }
+
+            if machine.halted() {
+                return Ok(Status::Halted)
+            }
+
+            self.step(machine)?;
+        }
+
+        Ok(if until(machine) { Status::Until } else { Status::MaxCycles })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new();
+        machine.load(&[16, 0xfde8]).unwrap(); // @16, AM=M+1
+        machine.ram[16] = 41;
+        machine
+    }
+
+    #[test]
+    fn test_record_step() {
+        let mut machine = machine();
+
+        assert_eq!(Record::step(&mut machine).write, None);
+        assert_eq!(Record::step(&mut machine), Record {
+            cycle: 1, pc: 1, instruction: 0xfde8, a: 42, d: 0, write: Some((16, 42)),
+        });
+    }
+
+    #[test]
+    fn test_text() {
+        let mut machine = machine();
+        machine.step();
+
+        assert_eq!(Record::step(&mut machine).to_string(), "       1  PC     1  AM=M+1      A     42  D      0  RAM[16] 42");
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut machine = machine();
+        let records = [Record::step(&mut machine), Record::step(&mut machine)];
+
+        for record in records.iter() {
+            assert_eq!(Record::decode(&record.encode()), Some(*record));
+        }
+
+        assert_eq!(Record::decode(&[0; Record::SIZE - 1]), None);
+    }
+}
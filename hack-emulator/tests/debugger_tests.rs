@@ -0,0 +1,188 @@
+//! Tests!
+
+use std::fs;
+
+use emulator::{Debugger, Machine};
+
+
+// Assembly as CodeWriter writes `call`, `return` and `push argument 0`
+fn call(function: &str, args: usize, n: usize) -> String {
+    format!("\
+@RETURN_{n}\nD=A\n@SP\nM=M+1\nA=M-1\nM=D
+@LCL\nD=M\n@SP\nM=M+1\nA=M-1\nM=D
+@ARG\nD=M\n@SP\nM=M+1\nA=M-1\nM=D
+@THIS\nD=M\n@SP\nM=M+1\nA=M-1\nM=D
+@THAT\nD=M\n@SP\nM=M+1\nA=M-1\nM=D
+@SP\nD=M\n@{frame}\nD=D-A\n@ARG\nM=D
+@SP\nD=M\n@LCL\nM=D
+@{function}\n0;JMP
+(RETURN_{n})
+", n = n, frame = args + 5, function = function)
+}
+
+const RETURN: &str = "\
+@LCL\nD=M\n@R13\nM=D
+@5\nA=D-A\nD=M\n@R14\nM=D
+@SP\nM=M-1\nA=M\nD=M\n@ARG\nA=M\nM=D
+@ARG\nD=M+1\n@SP\nM=D
+@R13\nAM=M-1\nD=M\n@THAT\nM=D
+@R13\nAM=M-1\nD=M\n@THIS\nM=D
+@R13\nAM=M-1\nD=M\n@ARG\nM=D
+@R13\nAM=M-1\nD=M\n@LCL\nM=D
+@R14\nA=M\n0;JMP
+";
+
+const PUSH_ARGUMENT_0: &str = "@ARG\nA=M\nD=M\n@SP\nM=M+1\nA=M-1\nM=D\n";
+
+// Sys.init calls Main.twice(3), which calls Main.double(3)
+fn program() -> String {
+    let mut asm = String::from("@256\nD=A\n@SP\nM=D\n");
+    asm += &call("Sys.init", 0, 1);
+    asm += "(Sys.init)\n@3\nD=A\n@SP\nM=M+1\nA=M-1\nM=D\n";
+    asm += &call("Main.twice", 1, 2);
+    asm += "(END)\n@END\n0;JMP\n";
+    asm += "(Main.twice)\n";
+    asm += PUSH_ARGUMENT_0;
+    asm += &call("Main.double", 1, 3);
+    asm += RETURN;
+    asm += "(Main.double)\n";
+    asm += PUSH_ARGUMENT_0;
+    asm += PUSH_ARGUMENT_0;
+    asm += "@SP\nAM=M-1\nD=M\nA=A-1\nM=D+M\n";
+    asm += RETURN;
+    asm
+}
+
+fn debugger(name: &str) -> Debugger {
+    let dir = std::env::temp_dir().join("hack-emulator-debugger-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(name);
+    fs::write(&path, program()).unwrap();
+
+    let mut machine = Machine::new();
+    let symbols = emulator::load(&mut machine, &path).unwrap();
+
+    Debugger::new(machine, symbols)
+}
+
+
+#[test]
+fn test_break_at_label_and_backtrace() {
+    let mut debugger = debugger("Backtrace.asm");
+
+    assert!(debugger.execute("break Main.double").unwrap().ends_with("(Main.double)"));
+
+    let out = debugger.execute("continue").unwrap();
+    assert!(out.starts_with("Breakpoint\n"), "{}", out);
+    assert!(out.contains("  Main.double  "), "{}", out);
+
+    let backtrace = debugger.execute("backtrace").unwrap();
+    let functions: Vec<&str> = backtrace.lines().map(|l| l.split_whitespace().nth(1).unwrap()).collect();
+
+    // Callers are at their return addresses, after each call's 49 words.
+    // The bootstrap's return address is Sys.init itself
+    assert_eq!(functions, vec!("Main.double", "Main.twice+49", "Sys.init+48", "Sys.init"));
+    assert!(backtrace.starts_with("#0  Main.double at 188  LCL=273 ARG=267\n#1  Main.twice+49 at 145  LCL=267 ARG=261"), "{}", backtrace);
+}
+
+#[test]
+fn test_next_steps_over_call() {
+    let mut debugger = debugger("Next.asm");
+    debugger.execute("break Main.twice").unwrap();
+    debugger.execute("continue").unwrap();
+
+    // Step to the jump into Main.double
+    while debugger.machine.rom[debugger.machine.pc as usize] != 0xea87 {
+        debugger.execute("step").unwrap();
+    }
+
+    let pc = debugger.machine.pc;
+    debugger.execute("next").unwrap();
+
+    assert_eq!(debugger.machine.pc, pc + 1);
+    assert_eq!(debugger.execute("print SP").unwrap(), "SP = RAM[0] = 268");
+    assert_eq!(debugger.execute("print RAM[266..267]").unwrap(), "RAM[266] = 0\nRAM[267] = 6");
+    assert_eq!(debugger.execute("print RAM[32766..32767]").unwrap(), "RAM[32766] = 0\nRAM[32767] = 0");
+}
+
+#[test]
+fn test_watch_stops_at_write() {
+    let mut debugger = debugger("Watch.asm");
+
+    assert_eq!(debugger.execute("watch RAM[SP]").unwrap(), "Watching RAM[0] = 0");
+
+    let out = debugger.execute("continue").unwrap();
+    assert!(out.starts_with("RAM[0]: 0 -> 256\n"), "{}", out);
+    assert_eq!(debugger.execute("print D").unwrap(), "D = 256");
+}
+
+#[test]
+fn test_continue_to_halt() {
+    let mut debugger = debugger("Halt.asm");
+
+    assert!(debugger.execute("c").unwrap().starts_with("Program halted\n"));
+    assert_eq!(debugger.execute("print RAM[261]").unwrap(), "RAM[261] = 6");
+}
+
+#[test]
+fn test_errors() {
+    let mut debugger = debugger("Errors.asm");
+
+    assert_eq!(debugger.execute("break NOWHERE"), Err(String::from("Unknown label or ROM address 'NOWHERE'")));
+    assert_eq!(debugger.execute("frobnicate"), Err(String::from("Unknown command 'frobnicate'")));
+}
+
+#[test]
+fn test_reverse_step() {
+    let mut debugger = debugger("ReverseStep.asm");
+
+    assert!(debugger.execute("reverse-step").unwrap().starts_with("No more reverse-execution history\n"));
+
+    debugger.execute("step 10").unwrap();
+    let location = debugger.location();
+    debugger.execute("step 3").unwrap();
+
+    assert_eq!(debugger.execute("reverse-step 3").unwrap(), location);
+    assert_eq!(debugger.machine.cycles, 10);
+    assert!(debugger.execute("rs 20").unwrap().starts_with("No more reverse-execution history\n"));
+    assert_eq!(debugger.machine.cycles, 0);
+}
+
+#[test]
+fn test_reverse_continue_to_breakpoint() {
+    let mut debugger = debugger("ReverseBreak.asm");
+    debugger.execute("continue").unwrap();
+    let halted = debugger.machine.cycles;
+
+    debugger.execute("break Main.double").unwrap();
+    let out = debugger.execute("reverse-continue").unwrap();
+
+    assert!(out.starts_with("Breakpoint\n"), "{}", out);
+    assert!(out.contains("  Main.double  "), "{}", out);
+    assert_eq!(debugger.execute("print ARG").unwrap(), "ARG = RAM[2] = 267");
+
+    // Forward again, to the same end
+    assert!(debugger.execute("continue").unwrap().starts_with("Program halted\n"));
+    assert_eq!(debugger.machine.cycles, halted);
+}
+
+#[test]
+fn test_reverse_continue_to_watched_change_and_last_write() {
+    let mut debugger = debugger("ReverseWatch.asm");
+    debugger.execute("continue").unwrap();
+
+    // Main.twice returns 6 over its argument, 3
+    let out = debugger.execute("last-write RAM[261]").unwrap();
+    let (change, location) = out.split_once('\n').unwrap();
+    let cycle: u64 = change.strip_prefix("RAM[261]: 3 -> 6 at cycle ").unwrap().parse().unwrap();
+    assert!(location.contains("  RETURN_3+"), "{}", out);
+
+    // Back to just after the write, then before it
+    debugger.execute("watch RAM[261]").unwrap();
+    assert_eq!(debugger.execute("reverse-continue").unwrap(), debugger.location());
+    assert_eq!(debugger.machine.cycles, cycle + 1);
+
+    assert!(debugger.execute("reverse-step").unwrap().starts_with("RAM[261]: 6 -> 3\n"));
+    assert_eq!(debugger.execute("last-write RAM[261]").unwrap().lines().next(), Some("RAM[261]: 0 -> 3 at cycle 51"));
+}
@@ -0,0 +1,138 @@
+//! Tests!
+
+use std::fs;
+use std::io::Cursor;
+
+use emulator::{gdb, Debugger, Machine};
+
+
+// Counts RAM[16] down from 7, then halts at END (9)
+const COUNTDOWN: &str = "\
+@7\nD=A\n@count\nM=D
+(LOOP)\n@count\nM=M-1\nD=M\n@LOOP\nD;JGT
+(END)\n@END\n0;JMP
+";
+
+fn packet(data: &str) -> String {
+    format!("${}#{:02x}", data, gdb::checksum(data.as_bytes()))
+}
+
+fn hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Serve `input`, returning each packet sent back, and "-" for each
+// rejected packet
+fn session(name: &str, input: String) -> Vec<String> {
+    let dir = std::env::temp_dir().join("hack-emulator-gdb-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(name);
+    fs::write(&path, COUNTDOWN).unwrap();
+
+    let mut machine = Machine::new();
+    let symbols = emulator::load(&mut machine, &path).unwrap();
+    let mut debugger = Debugger::new(machine, symbols);
+    let mut output = Vec::new();
+
+    gdb::serve(&mut debugger, Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut replies = Vec::new();
+    let mut rest = output.as_str();
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '$' => {
+                let end = rest.find('#').unwrap();
+                replies.push(rest[1..end].to_string());
+                rest = &rest[end + 3..];
+            },
+            '-' => {
+                replies.push(String::from("-"));
+                rest = &rest[1..];
+            },
+            _ => rest = &rest[1..],
+        }
+    }
+
+    replies
+}
+
+fn packets(packets: &[&str]) -> String {
+    packets.iter().map(|p| packet(p)).collect()
+}
+
+#[test]
+fn test_break_continue_and_registers() {
+    let replies = session("Break.asm", packets(&["?", "Z0,9,2", "c", "g", "p2", "z0,9,2", "k"]));
+
+    // A is the last @LOOP, 4; D is the count, 0; PC is END, 9
+    assert_eq!(replies, vec!("S05", "OK", "S05", "040000000900", "0900", "OK"));
+}
+
+#[test]
+fn test_step_and_set_registers() {
+    let replies = session("Step.asm", packets(&["s", "s", "g", "P1=ffff", "P2=0400", "g", "s", "p0", "D"]));
+
+    assert_eq!(replies, vec!(
+        "S05", "S05", "070007000200", "OK", "OK", "0700ffff0400", "S05", "1000", "OK",
+    ));
+}
+
+#[test]
+fn test_memory_reads_and_writes() {
+    // RAM[16] at byte 0x20, ROM[0] at byte 0x10000
+    let replies = session("Memory.asm", packets(&[
+        "m20,2", "s", "s", "s", "s", "m20,2", "M20,2:0300", "m1f,4", "m10000,4", "m20000,2", "k",
+    ]));
+
+    assert_eq!(replies[0], "0000");
+    assert_eq!(replies[5], "0700");
+    assert_eq!(replies[6..], ["OK", "00030000", "070010ec", "E01"]);
+}
+
+#[test]
+fn test_continue_to_halt_and_monitor() {
+    let replies = session("Halt.asm", packets(&[
+        "QStartNoAckMode", "c", &format!("qRcmd,{}", hex("print count")), "k",
+    ]));
+
+    assert_eq!(replies, vec!(
+        String::from("OK"),
+        format!("O{}", hex("Program halted\n")),
+        String::from("S05"),
+        format!("O{}", hex("count = RAM[16] = 0\n")),
+        String::from("OK"),
+    ));
+}
+
+#[test]
+fn test_bad_checksum_is_rejected() {
+    let replies = session("Checksum.asm", format!("$g#00{}", packet("p0")));
+
+    assert_eq!(replies, vec!("-", "0000"));
+}
+
+#[test]
+fn test_target_description() {
+    let replies = session("Features.asm", packets(&["qSupported:xmlRegisters=i386", "qXfer:features:read:target.xml:0,1000"]));
+
+    assert!(replies[0].contains("qXfer:features:read+"));
+    assert!(replies[1].starts_with("l<?xml"));
+    assert!(replies[1].contains(r#"<reg name="pc" bitsize="16""#));
+}
+
+#[test]
+fn test_reverse_step_and_continue() {
+    let replies = session("Reverse.asm", packets(&[
+        "bs", "s", "s", "s", "bs", "p2", "Z0,4,2", "c", "c", "c", "m20,2", "bc", "m20,2", "bc", "p2", "k",
+    ]));
+
+    // Forward to the end of the history, to the second time round LOOP,
+    // back to the first, then no further
+    assert_eq!(replies, vec!(
+        "T05replaylog:begin;", "S05", "S05", "S05", "S05", "0200", "OK", "T05replaylog:end;", "S05", "S05", "0600",
+        "S05", "0700", "T05replaylog:begin;", "0000",
+    ));
+}
@@ -0,0 +1,162 @@
+//! Tests!
+
+use std::fs;
+
+use assembler::assemble_str;
+use emulator::{ErrorKind, Machine, Status};
+
+
+const MULT: &str = "\
+// Computes R2 = R0 * R1
+@R2
+M=0
+(LOOP)
+@R1
+D=M
+@END
+D;JLE
+@R0
+D=M
+@R2
+M=D+M
+@R1
+M=M-1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+fn machine(assembly: &str) -> Machine {
+    let mut machine = Machine::new();
+    machine.load(&assemble_str(assembly).unwrap()).unwrap();
+    machine
+}
+
+
+#[test]
+fn test_run_mult() {
+    let mut machine = machine(MULT);
+    machine.ram[0] = 6;
+    machine.ram[1] = 7;
+
+    assert_eq!(machine.run(1000), Status::Halted);
+    assert_eq!(machine.ram[2], 42);
+}
+
+#[test]
+fn test_run_signed_arithmetic() {
+    let mut machine = machine("@3\nD=-A\n@5\nD=D-A\n@R0\nM=D\n@R0\nM=!M\n");
+
+    machine.run(8);
+
+    assert_eq!((machine.d as i16, machine.ram[0]), (-8, 7));
+}
+
+#[test]
+fn test_step_counts_cycles() {
+    let mut machine = machine("@1\nD=A\n");
+
+    machine.step();
+    machine.step();
+
+    assert_eq!((machine.cycles, machine.pc, machine.d), (2, 2, 1));
+}
+
+#[test]
+fn test_load_hack() {
+    let dir = std::env::temp_dir().join("hack-emulator-machine-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("Add.hack");
+    fs::write(&path, "0000000000000010\n1110110000010000\n0000000000000011\n1110000010010000\n").unwrap();
+
+    let mut machine = Machine::new();
+    machine.load_hack(&path).unwrap();
+    machine.run(4);
+
+    assert_eq!(machine.d, 5);
+}
+
+#[test]
+fn test_load_hack_error_is_in_file() {
+    let dir = std::env::temp_dir().join("hack-emulator-machine-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("Bad.hack");
+    fs::write(&path, "0000000000000010\n@2\n").unwrap();
+
+    let e = Machine::new().load_hack(&path).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::UnrecognisedWord));
+    assert_eq!(e.to_string(), format!("{}:2: error: Expected 16 '0'/'1' characters '@2'", path.display()));
+}
+
+#[test]
+fn test_load_too_large() {
+    let e = Machine::new().load(&vec![0; 0x8001]).unwrap_err();
+
+    assert!(matches!(e.kind, ErrorKind::ProgramTooLarge));
+}
+
+#[test]
+fn test_keyboard_script_drives_kbd() {
+    // Counts key presses into R0, waiting for each key to be released
+    let mut machine = machine("\
+(WAIT)
+@KBD
+D=M
+@WAIT
+D;JEQ
+@R0
+M=M+1
+(RELEASE)
+@KBD
+D=M
+@RELEASE
+D;JNE
+@WAIT
+0;JMP
+");
+    machine.keyboard = "100..200 \"ab\"\n1000..1100 ENTER\n".parse().unwrap();
+
+    machine.run(2000);
+
+    assert_eq!((machine.ram[0], machine.ram[0x6000]), (3, 0));
+}
+
+#[test]
+fn test_halts_at_self_jump() {
+    let mut machine = machine("@5\nD=A\n(LOOP)\n@LOOP\nD;JEQ\n(END)\n@END\n0;JMP\n");
+
+    assert_eq!(machine.run(100), Status::Halted);
+    assert_eq!((machine.pc, machine.cycles), (5, 5));
+}
+
+#[test]
+fn test_jump_that_writes_has_not_halted() {
+    let mut machine = machine("(LOOP)\n@LOOP\nM=M+1;JMP\n");
+
+    assert_eq!(machine.run(100), Status::MaxCycles);
+    assert_eq!(machine.cycles, 100);
+}
+
+#[test]
+fn test_waiting_for_keys_has_not_halted() {
+    let mut machine = machine("(WAIT)\n@KBD\nD=M\n@WAIT\nD;JEQ\n(END)\n@END\n0;JMP\n");
+    machine.keyboard = "50..60 65\n".parse().unwrap();
+
+    assert_eq!(machine.run(1000), Status::Halted);
+    assert!(machine.cycles > 50);
+}
+
+#[test]
+fn test_run_until() {
+    let mut machine = machine(MULT);
+    machine.ram[0] = 6;
+    machine.ram[1] = 7;
+
+    assert_eq!(machine.run_until(1000, |m| m.ram[2] >= 18), Status::Until);
+    assert_eq!(machine.ram[2], 18);
+}
@@ -0,0 +1,106 @@
+//! Tests!
+
+use std::fs;
+use std::path::PathBuf;
+
+use emulator::{run_script, ErrorKind};
+
+
+const MULT: &str = "\
+// Computes R2 = R0 * R1
+@R2
+M=0
+(LOOP)
+@R1
+D=M
+@END
+D;JLE
+@R0
+D=M
+@R2
+M=D+M
+@R1
+M=M-1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+const MULT_TST: &str = "\
+// Mult.tst, as shipped with the course
+load Mult.asm,
+output-file Mult.out,
+compare-to Mult.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+set RAM[0] 0, set RAM[1] 0;
+repeat 20 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 3, set RAM[1] -2;
+repeat 50 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 6, set RAM[1] 7;
+while PC <> 14 {
+  ticktock;
+}
+output;
+";
+
+const MULT_CMP: &str = "\
+|  RAM[0]  |  RAM[1]  |  RAM[2]  |
+|       0  |       0  |       0  |
+|       3  |      -2  |       0  |
+|       6  |       0  |      42  |
+";
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("hack-emulator-script-tests").join(name);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("Mult.asm"), MULT).unwrap();
+    fs::write(dir.join("Mult.tst"), MULT_TST).unwrap();
+    dir
+}
+
+
+#[test]
+fn test_run_script_compares_output() {
+    let dir = dir("pass");
+    fs::write(dir.join("Mult.cmp"), MULT_CMP).unwrap();
+
+    run_script(&dir.join("Mult.tst")).unwrap();
+
+    assert_eq!(fs::read_to_string(dir.join("Mult.out")).unwrap(), MULT_CMP);
+}
+
+#[test]
+fn test_run_script_reports_first_difference() {
+    let dir = dir("fail");
+    fs::write(dir.join("Mult.cmp"), MULT_CMP.replace("     42", "     41")).unwrap();
+
+    let e = run_script(&dir.join("Mult.tst")).unwrap_err();
+
+    assert!(matches!(&e.kind, ErrorKind::ComparisonFailure(expected) if expected.contains("41")));
+    assert_eq!((e.line, e.file), (4, Some(dir.join("Mult.out").to_string_lossy().into_owned())));
+    assert_eq!(fs::read_to_string(dir.join("Mult.out")).unwrap(), MULT_CMP);
+}
+
+#[test]
+fn test_run_script_error_is_in_script() {
+    let dir = dir("error");
+    fs::write(dir.join("Bad.tst"), "load Mult.asm,\nset X 1;\n").unwrap();
+
+    let e = run_script(&dir.join("Bad.tst")).unwrap_err();
+
+    assert_eq!(e.to_string(), format!("{}:2: error: Invalid test script command 'X'", dir.join("Bad.tst").display()));
+}
@@ -0,0 +1,99 @@
+//! Tests!
+
+use std::fs;
+
+use emulator::{Machine, Record, Status, Trace, TraceFormat};
+
+
+const MULT: &str = "\
+// Computes R2 = R0 * R1
+@R2
+M=0
+(LOOP)
+@R1
+D=M
+@END
+D;JLE
+@R0
+D=M
+@R2
+M=D+M
+@R1
+M=M-1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+// MULT of 3 * 2, loaded with its symbols
+fn mult(name: &str) -> (Machine, emulator::Symbols) {
+    let dir = std::env::temp_dir().join("hack-emulator-trace-tests");
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(name);
+    fs::write(&path, MULT).unwrap();
+
+    let mut machine = Machine::new();
+    let symbols = emulator::load(&mut machine, &path).unwrap();
+    machine.ram[0] = 3;
+    machine.ram[1] = 2;
+
+    (machine, symbols)
+}
+
+fn run(machine: &mut Machine, trace: &mut Trace<&mut Vec<u8>>) {
+    assert_eq!(trace.run_until(machine, 1000, |_| false).unwrap(), Status::Halted);
+}
+
+
+#[test]
+fn test_text_trace() {
+    let (mut machine, _) = mult("Text.asm");
+    let mut output = Vec::new();
+
+    run(&mut machine, &mut Trace::new(&mut output, TraceFormat::Text));
+
+    let text = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines.len() as u64, machine.cycles);
+    assert_eq!(lines[0], "       0  PC     0  @2          A      2  D      0");
+    assert_eq!(lines[1], "       1  PC     1  M=0         A      2  D      0  RAM[2] 0");
+    assert_eq!(lines[9], "       9  PC     9  M=D+M       A      2  D      3  RAM[2] 3");
+}
+
+#[test]
+fn test_binary_trace_matches_text() {
+    let (mut machine, _) = mult("Binary.asm");
+    let (mut again, _) = mult("Binary.asm");
+    let mut binary = Vec::new();
+    let mut text = Vec::new();
+
+    run(&mut machine, &mut Trace::new(&mut binary, TraceFormat::Binary));
+    run(&mut again, &mut Trace::new(&mut text, TraceFormat::Text));
+
+    let decoded: Vec<String> = binary.chunks(Record::SIZE)
+        .map(|r| format!("{}\n", Record::decode(r).unwrap()))
+        .collect();
+
+    assert_eq!(binary.len() as u64, machine.cycles * Record::SIZE as u64);
+    assert_eq!(decoded.concat(), String::from_utf8(text).unwrap());
+}
+
+#[test]
+fn test_trace_only_included_pcs() {
+    let (mut machine, symbols) = mult("Filter.asm");
+    let (start, end) = symbols.extent("END").unwrap();
+    let mut output = Vec::new();
+    let mut trace = Trace::new(&mut output, TraceFormat::Binary);
+
+    trace.include(0, 2);
+    trace.include(start, end);
+    run(&mut machine, &mut trace);
+
+    let pcs: Vec<u16> = output.chunks(Record::SIZE).map(|r| Record::decode(r).unwrap().pc).collect();
+
+    assert_eq!(pcs, vec!(0, 1, 14));
+}